@@ -1,85 +1,484 @@
+use crate::budget::{CrawlBudget, CrawlOutcome, Spent};
 use crate::crawl_progress::CrawlProgress;
 use crate::curiosity::Curiosity;
-use crate::lookup::{Lookup, LookupError};
+use crate::lookup::{Lookup, LookupError, LookupErrorKind};
+use crate::provenance::{self, Provenance};
 use crate::rdf_graph::Graph;
+use crate::retry::RetryPolicy;
 use crate::store::Store;
 use crate::util::as_named_node;
+use alloc::collections::BTreeSet;
+use core::fmt;
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures_timer::Delay;
 use oxigraph::model as om;
 use oxigraph::sparql::{algebra::Query, EvaluationError, QueryResults};
+use std::time::{Duration, SystemTime};
+
+/// Number of lookups an agent will have in flight at once unless told otherwise.
+const DEFAULT_CONCURRENCY: usize = 1;
+
+/// Priority given to failed documents when they are retried. This is the priority of a document
+/// found by an unweighted curiosity query.
+const RETRY_PRIORITY: f64 = 1.0;
+
+/// Priority given to stale documents when they are looked up again.
+const REFRESH_PRIORITY: f64 = 1.0;
+
+/// A document the agent is curious about, its priority and, the first time it is found, why.
+type Curious = (om::NamedNode, f64, Option<Provenance>);
+
+/// The reasons an agent may be unable to carry on.
+#[derive(Debug)]
+pub enum AgentError<E> {
+    /// A document passed to [`Agent::investigate`] could not be looked up.
+    Lookup(LookupError),
+    /// A curiosity query could not be evaluated.
+    Query(EvaluationError),
+    /// The knowledge graph could not be read or written.
+    Store(E),
+}
+
+impl<E> From<EvaluationError> for AgentError<E> {
+    fn from(e: EvaluationError) -> Self {
+        AgentError::Query(e)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for AgentError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgentError::Lookup(e) => write!(f, "lookup failed: {}", e),
+            AgentError::Query(e) => write!(f, "curiosity query failed: {}", e),
+            AgentError::Store(e) => write!(f, "knowledge graph storage failed: {}", e),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for AgentError<E> {}
 
 pub struct Agent<S, L> {
     curiosity: Curiosity,
     knowlege_graph: S,
     progress: CrawlProgress,
     lookup: L,
+    concurrency: usize,
+    budget: CrawlBudget,
+    spent: Spent,
+    retry: RetryPolicy,
+    /// Graphs added to the knowledge graph since curiosity was last evaluated, or None if
+    /// curiosity must be evaluated against the whole knowledge graph.
+    fresh: Option<Vec<om::NamedOrBlankNode>>,
+    /// Whether curiosity may be evaluated against `fresh` alone.
+    incremental: bool,
+    /// Where to record why each document was looked up, if anywhere.
+    provenance_graph: Option<om::NamedNode>,
+    /// How long a document may go without being looked up again, if it should be looked up again
+    /// at all.
+    max_age: Option<Duration>,
 }
 
 impl<S: Store, L: Lookup> Agent<S, L> {
+    /// Where it can, the agent evaluates curiosity incrementally, against only the graphs it has
+    /// written since curiosity was last evaluated. Writes made to `knowlege_graph` some other way,
+    /// e.g. through a clone of a `MemoryStore`, go unnoticed until
+    /// [`invalidate_curiosity`](Self::invalidate_curiosity) is called, unless incremental
+    /// evaluation is turned off with [`with_incremental`](Self::with_incremental).
     pub fn new(curiosity: Curiosity, knowlege_graph: S, lookup: L) -> Self {
+        Self::resume(curiosity, knowlege_graph, lookup, Default::default())
+    }
+
+    /// Continue a crawl from a saved snapshot of its progress. `knowlege_graph` should be the
+    /// store the original crawl was writing to. The store may be ahead of the snapshot; documents
+    /// stored since the snapshot was taken are looked up again and replaced.
+    ///
+    /// As with [`new`](Self::new), curiosity is evaluated incrementally and assumes the agent is
+    /// the only writer to the store.
+    pub fn resume(
+        curiosity: Curiosity,
+        knowlege_graph: S,
+        lookup: L,
+        progress: CrawlProgress,
+    ) -> Self {
         Self {
             curiosity,
             knowlege_graph,
-            progress: Default::default(),
+            progress,
             lookup,
+            concurrency: DEFAULT_CONCURRENCY,
+            budget: CrawlBudget::default(),
+            spent: Spent::start(),
+            retry: RetryPolicy::default(),
+            fresh: None,
+            incremental: true,
+            provenance_graph: None,
+            max_age: None,
         }
     }
 
-    pub async fn investigate(&mut self, document: om::NamedNode) -> Result<(), LookupError> {
-        match self.lookup.lookup(&document).await {
-            Ok(content) => {
-                self.note_document_contents(document.clone(), content);
-                self.progress.visit(document);
-                Ok(())
+    /// Set the maximum number of documents looked up in parallel during a single round.
+    /// Values less than one are treated as one.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Limit the work done by subsequent calls to [`crawl`](Self::crawl). Each crawl starts with
+    /// a fresh budget. Calls to [`next`](Self::next) outside of a crawl share the budget of the
+    /// last crawl, or of the agent's lifetime if there has been none.
+    pub fn with_budget(mut self, budget: CrawlBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Decide which failed lookups are attempted again in later rounds.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Write the reason each document was looked up into `graph` of the knowledge graph, as
+    /// described in [`Provenance::to_quads`]. Curiosity queries will see these quads too.
+    pub fn with_provenance_graph(mut self, graph: om::NamedNode) -> Self {
+        self.provenance_graph = Some(graph);
+        self
+    }
+
+    /// Look up documents again once it has been `max_age` since they were last looked up,
+    /// replacing their contents in the knowledge graph.
+    ///
+    /// With a max age there is always something left to do, so [`crawl`](Self::crawl) only
+    /// returns when the budget runs out. Between refreshes it waits for the next document to
+    /// become stale.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Whether to evaluate curiosity against only the graphs added since it was last evaluated,
+    /// where the queries allow it. Turn this off if the store is written to by anything other
+    /// than the agent.
+    pub fn with_incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    /// Evaluate curiosity against the whole knowledge graph next time, e.g. after writing to the
+    /// store other than through the agent.
+    pub fn invalidate_curiosity(&mut self) {
+        self.fresh = None;
+    }
+
+    pub fn progress(&self) -> &CrawlProgress {
+        &self.progress
+    }
+
+    /// Which curiosity query result first made the agent curious about `document`. Documents
+    /// passed directly to [`investigate`](Self::investigate) have no explanation.
+    pub fn explain(&self, document: &om::NamedNode) -> Option<&Provenance> {
+        self.progress.explain(document)
+    }
+
+    pub async fn investigate(
+        &mut self,
+        document: om::NamedNode,
+    ) -> Result<(), AgentError<S::Error>> {
+        let result = self.lookup.lookup(&document).await;
+        self.spent.documents += 1;
+        record(
+            &mut self.knowlege_graph,
+            &mut self.progress,
+            &mut self.spent,
+            &mut self.fresh,
+            &self.provenance_graph,
+            document,
+            result,
+        )
+    }
+
+    /// Failed documents the retry policy allows to be attempted again, and when.
+    fn retries(&self) -> impl Iterator<Item = (om::NamedNode, SystemTime)> + '_ {
+        self.progress.failures().filter_map(move |(iri, failure)| {
+            let due = self.retry.retry_at(failure)?;
+            Some((om::NamedNode::new_unchecked(iri), due))
+        })
+    }
+
+    /// Visited documents that should be looked up again, and when.
+    fn refreshes(&self) -> impl Iterator<Item = (om::NamedNode, SystemTime)> + '_ {
+        self.max_age
+            .into_iter()
+            .flat_map(move |max_age| self.progress.expiries(max_age))
+            .map(|(iri, due)| (om::NamedNode::new_unchecked(iri), due))
+    }
+
+    /// Novel documents the agent is curious about, with their priorities and the reason for the
+    /// curiosity. Only the first reason found for each document is explained. None if the
+    /// preconditions of curiosity do not hold.
+    fn curious(&self) -> Result<Option<Vec<Curious>>, EvaluationError> {
+        let mut ret = Vec::new();
+        let mut explained = BTreeSet::new();
+        let fresh = self.fresh.as_deref().filter(|_| self.incremental);
+        let evaluated =
+            self.curiosity
+                .curious(&self.knowlege_graph, fresh, |term, priority, explain| {
+                    if let Some(nn) = as_named_node(term).filter(|nn| self.progress.novel(nn)) {
+                        let provenance = if self.progress.explain(nn).is_none()
+                            && explained.insert(nn.as_str().to_string())
+                        {
+                            Some(explain())
+                        } else {
+                            None
+                        };
+                        ret.push((nn.clone(), priority, provenance));
+                    }
+                })?;
+        Ok(Some(ret).filter(|_| evaluated))
+    }
+
+    /// Crawl until curiosity is exhausted or the budget runs out, reporting which happened.
+    /// Each call to crawl starts with a fresh budget.
+    pub async fn crawl(&mut self) -> Result<CrawlOutcome, AgentError<S::Error>> {
+        self.spent = Spent::start();
+        loop {
+            if let Some(limit) = self.spent.exceeded(&self.budget) {
+                return Ok(limit);
             }
-            Err(e) => {
-                self.progress.error(document);
-                Err(e)
+            if !self.next().await? {
+                return Ok(CrawlOutcome::Exhausted);
             }
         }
     }
 
-    fn curious(&self) -> Result<Vec<om::NamedNode>, EvaluationError> {
-        let mut ret = Vec::new();
-        self.curiosity.curious(&self.knowlege_graph, |term| {
-            as_named_node(term)
-                .filter(|nn| self.progress.novel(nn))
-                .map(|nn| ret.push(nn.clone()));
-        })?;
-        Ok(ret)
-    }
-
-    fn note_document_contents(&mut self, document: om::NamedNode, contents: Graph) {
-        for triple in contents {
-            self.knowlege_graph
-                .insert(triple.in_graph(document.clone()));
+    /// Look up every document the agent is currently curious about, along with any failed
+    /// documents that are due to be retried, any stale documents and any left over from a
+    /// previous round. Documents are started in order of priority and at most `concurrency`
+    /// lookups are in flight at once; results are merged into the knowledge graph in the order
+    /// they complete. No new lookups are started once the budget is exceeded, so under a budget
+    /// the most interesting documents are looked up first.
+    ///
+    /// If there is nothing to look up but some failed documents will be retried or some
+    /// documents will become stale later, waits until the first of them is due.
+    ///
+    /// Documents whose contents could not be stored, or whose provenance could not be checked,
+    /// are put back on the frontier. Once that happens no new lookups are started, and the storage
+    /// error is returned after the lookups in flight are done. Failed lookups are noted in the crawl progress rather than returned.
+    ///
+    /// Returns false if there was nothing left to be curious about or the budget is exhausted.
+    pub async fn next(&mut self) -> Result<bool, AgentError<S::Error>> {
+        if self.spent.exceeded(&self.budget).is_some() {
+            return Ok(false);
+        }
+        let curious = match self.curious()? {
+            Some(curious) => {
+                self.fresh = Some(Vec::new());
+                curious
+            }
+            // Curiosity queries were skipped, so graphs added since they last ran are still fresh.
+            None => Vec::new(),
+        };
+        debug_assert!(curious.iter().all(|(nn, _, _)| self.progress.novel(nn)));
+        for (nn, priority, provenance) in curious {
+            if let Some(provenance) = provenance {
+                self.progress.note_provenance(&nn, provenance);
+            }
+            self.progress.enqueue(nn, priority);
+        }
+        if self.progress.frontier().next().is_none() {
+            let first_due = match self
+                .retries()
+                .chain(self.refreshes())
+                .map(|(_, due)| due)
+                .min()
+            {
+                Some(due) => due,
+                None => return Ok(false),
+            };
+            let mut wait = first_due
+                .duration_since(SystemTime::now())
+                .unwrap_or_default();
+            if let Some(remaining) = self.spent.remaining_time(&self.budget) {
+                wait = wait.min(remaining);
+            }
+            Delay::new(wait).await;
+        }
+        let now = SystemTime::now();
+        let due: Vec<om::NamedNode> = self
+            .retries()
+            .filter(|(_, due)| *due <= now)
+            .map(|(nn, _)| nn)
+            .collect();
+        for nn in due {
+            self.progress.enqueue(nn, RETRY_PRIORITY);
+        }
+        let stale: Vec<om::NamedNode> = self
+            .refreshes()
+            .filter(|(_, due)| *due <= now)
+            .map(|(nn, _)| nn)
+            .collect();
+        for nn in stale {
+            self.progress.enqueue(nn, REFRESH_PRIORITY);
         }
-    }
 
-    pub async fn crawl(&mut self) -> Result<(), EvaluationError> {
-        while self.next().await? {}
-        Ok(())
+        let lookup = &self.lookup;
+        let mut in_flight = FuturesUnordered::new();
+        let mut failed = None;
+        loop {
+            while failed.is_none()
+                && in_flight.len() < self.concurrency
+                && self.spent.exceeded(&self.budget).is_none()
+            {
+                match self.progress.dequeue() {
+                    Some(nn) => {
+                        self.spent.documents += 1;
+                        in_flight.push(async move {
+                            let result = lookup.lookup(&nn).await;
+                            (nn, result)
+                        });
+                    }
+                    None => break,
+                }
+            }
+            match in_flight.next().await {
+                Some((nn, result)) => {
+                    let recorded = record(
+                        &mut self.knowlege_graph,
+                        &mut self.progress,
+                        &mut self.spent,
+                        &mut self.fresh,
+                        &self.provenance_graph,
+                        nn,
+                        result,
+                    );
+                    match recorded {
+                        Ok(()) | Err(AgentError::Lookup(_)) => {}
+                        Err(e) => {
+                            failed.get_or_insert(e);
+                        }
+                    }
+                }
+                None => break,
+            }
+        }
+        self.spent.rounds += 1;
+        match failed {
+            Some(e) => Err(e),
+            None => Ok(true),
+        }
     }
+}
 
-    pub async fn next(&mut self) -> Result<bool, EvaluationError> {
-        let curious = self.curious()?;
-        debug_assert!(curious.iter().all(|nn| self.progress.novel(nn)));
-        if curious.is_empty() {
-            return Ok(false);
+/// Merge the result of a lookup into the knowledge graph and note it in the crawl progress. The
+/// contents of documents looked up before are replaced.
+///
+/// A document's contents and provenance are committed to the store together, and only then is the
+/// document marked visited. A crawl interrupted in between looks the document up again when
+/// resumed, which replaces the contents rather than duplicating them, and provenance already in
+/// the store is not written twice.
+///
+/// The contents of a visited document that is no longer found are removed. If the store fails to
+/// be read or written, the document is put back on the frontier to be looked up again.
+fn record<S: Store>(
+    knowlege_graph: &mut S,
+    progress: &mut CrawlProgress,
+    spent: &mut Spent,
+    fresh: &mut Option<Vec<om::NamedOrBlankNode>>,
+    provenance_graph: &Option<om::NamedNode>,
+    document: om::NamedNode,
+    result: Result<Graph, LookupError>,
+) -> Result<(), AgentError<S::Error>> {
+    match result {
+        Ok(content) => {
+            let mut quads: Vec<om::Quad> = content
+                .into_iter()
+                .map(|t| t.in_graph(document.clone()))
+                .collect();
+            spent.triples += quads.len();
+            let unrecorded = match provenance_graph {
+                Some(graph) => match provenance::is_recorded(knowlege_graph, &document, graph) {
+                    Ok(recorded) => Some(graph).filter(|_| !recorded),
+                    Err(e) => {
+                        progress.enqueue(document, RETRY_PRIORITY);
+                        return Err(AgentError::Store(e));
+                    }
+                },
+                None => None,
+            };
+            let explained =
+                unrecorded.and_then(|graph| Some((graph, progress.explain(&document)?)));
+            if let Some((graph, provenance)) = explained {
+                quads.extend(provenance.to_quads(&document, graph));
+                if let Some(fresh) = fresh {
+                    fresh.push(graph.clone().into());
+                }
+            }
+            if let Err(e) = knowlege_graph.commit(&[document.clone().into()], quads) {
+                progress.enqueue(document, RETRY_PRIORITY);
+                return Err(AgentError::Store(e));
+            }
+            if let Some(fresh) = fresh {
+                fresh.push(document.clone().into());
+            }
+            progress.visit(document);
+            Ok(())
         }
-        for nn in &curious {
-            let _ = self.investigate(nn.clone()).await;
+        Err(e) => {
+            // A document that has gone away takes its old contents with it.
+            if e.kind() == LookupErrorKind::NotFound && progress.visited(&document) {
+                if let Err(e) = knowlege_graph.commit(&[document.clone().into()], Vec::new()) {
+                    progress.enqueue(document, RETRY_PRIORITY);
+                    return Err(AgentError::Store(e));
+                }
+            }
+            progress.error(document, e.clone());
+            Err(AgentError::Lookup(e))
         }
-        debug_assert!(!curious.iter().any(|nn| self.progress.novel(nn)));
-        Ok(true)
     }
 }
 
 impl<S: Store, L> Store for Agent<S, L> {
-    fn insert(&mut self, quad: om::Quad) {
+    // There is no telling which solutions an arbitrary change affects, so every mutation makes
+    // the agent evaluate curiosity against the whole knowledge graph again.
+
+    type Error = S::Error;
+
+    fn insert(&mut self, quad: om::Quad) -> Result<(), S::Error> {
+        self.fresh = None;
         Store::insert(&mut self.knowlege_graph, quad)
     }
 
+    fn remove(&mut self, quad: om::QuadRef<'_>) -> Result<(), S::Error> {
+        self.fresh = None;
+        Store::remove(&mut self.knowlege_graph, quad)
+    }
+
     fn query(&self, query: Query) -> Result<QueryResults, EvaluationError> {
         Store::query(&self.knowlege_graph, query)
     }
+
+    fn contains(&self, quad: om::QuadRef<'_>) -> Result<bool, S::Error> {
+        Store::contains(&self.knowlege_graph, quad)
+    }
+
+    fn len(&self) -> Result<usize, S::Error> {
+        Store::len(&self.knowlege_graph)
+    }
+
+    fn named_graphs(&self) -> Result<Vec<om::NamedOrBlankNode>, S::Error> {
+        Store::named_graphs(&self.knowlege_graph)
+    }
+
+    fn clear_graph(&mut self, graph: om::GraphNameRef<'_>) -> Result<(), S::Error> {
+        self.fresh = None;
+        Store::clear_graph(&mut self.knowlege_graph, graph)
+    }
+
+    fn keeps_default_graph(&self) -> bool {
+        self.knowlege_graph.keeps_default_graph()
+    }
+
+    fn commit(&mut self, clear: &[om::GraphName], quads: Vec<om::Quad>) -> Result<(), S::Error> {
+        self.fresh = None;
+        Store::commit(&mut self.knowlege_graph, clear, quads)
+    }
 }
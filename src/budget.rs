@@ -0,0 +1,74 @@
+//! Limits on how much work a crawl may do before it stops.
+
+use std::time::{Duration, Instant};
+
+/// Upper bounds on a crawl. A limit of `None` means unlimited. The default budget is unlimited.
+///
+/// Limits are checked between rounds and before each lookup is started. Lookups already in flight
+/// when a limit is reached are allowed to finish, so a crawl may overshoot `max_triples` and
+/// `max_duration` by the cost of at most `concurrency` documents.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CrawlBudget {
+    /// Maximum number of documents looked up, whether or not the lookup succeeded.
+    pub max_documents: Option<usize>,
+    /// Maximum number of triples written to the knowledge graph.
+    pub max_triples: Option<usize>,
+    /// Maximum wall-clock time spent crawling.
+    pub max_duration: Option<Duration>,
+    /// Maximum number of rounds of curiosity evaluation.
+    pub max_rounds: Option<usize>,
+}
+
+/// The reason a crawl stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrawlOutcome {
+    /// The agent is no longer curious about anything it has not already looked up.
+    Exhausted,
+    MaxDocuments,
+    MaxTriples,
+    MaxDuration,
+    MaxRounds,
+}
+
+/// What a crawl has used so far.
+#[derive(Debug)]
+pub(crate) struct Spent {
+    pub documents: usize,
+    pub triples: usize,
+    pub rounds: usize,
+    started: Instant,
+}
+
+impl Spent {
+    pub fn start() -> Self {
+        Self {
+            documents: 0,
+            triples: 0,
+            rounds: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Time left before `budget.max_duration` is reached, if there is such a limit.
+    pub fn remaining_time(&self, budget: &CrawlBudget) -> Option<Duration> {
+        budget
+            .max_duration
+            .map(|d| d.saturating_sub(self.started.elapsed()))
+    }
+
+    /// Returns the first limit in `budget` that has been reached, if any.
+    pub fn exceeded(&self, budget: &CrawlBudget) -> Option<CrawlOutcome> {
+        let reached = |used: usize, limit: Option<usize>| matches!(limit, Some(l) if used >= l);
+        if reached(self.documents, budget.max_documents) {
+            Some(CrawlOutcome::MaxDocuments)
+        } else if reached(self.triples, budget.max_triples) {
+            Some(CrawlOutcome::MaxTriples)
+        } else if reached(self.rounds, budget.max_rounds) {
+            Some(CrawlOutcome::MaxRounds)
+        } else if matches!(budget.max_duration, Some(d) if self.started.elapsed() >= d) {
+            Some(CrawlOutcome::MaxDuration)
+        } else {
+            None
+        }
+    }
+}
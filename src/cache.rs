@@ -0,0 +1,384 @@
+//! Keeping retrieved documents on disk so they survive the process that crawled them.
+
+use crate::fetch::{Fetch, Fetched, RawDocument};
+use crate::jsonld::{ContextCache, DocumentLoader};
+use crate::lookup::{Lookup, LookupError};
+use crate::rdf_graph::Graph;
+use async_trait::async_trait;
+use multihash::Code;
+use oxigraph::model as om;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Caches the raw documents retrieved by a [`Fetch`], e.g. an [`HttpLookup`](crate::HttpLookup),
+/// in a directory, one file per document. Any other [`Lookup`] can be cached by wrapping it in a
+/// [`LookupFetch`](crate::LookupFetch).
+///
+/// Cached documents are used for as long as their `Cache-Control: max-age` allows, or for the
+/// default max age (zero unless told otherwise) when they did not come with one. Stale documents
+/// that have an `ETag` are revalidated with a conditional request. Documents marked `no-store`
+/// are never written to disk and those marked `no-cache` are always revalidated.
+///
+/// In offline mode the inner fetch is never used. Every cached document is served regardless of
+/// its age, and documents missing from the cache are not found, so a crawl can be replayed
+/// exactly from the cache of an earlier one. Only documents are cached, not failures. Errors
+/// writing to the cache directory are ignored; the documents concerned are fetched again next
+/// time.
+pub struct CachingLookup<F> {
+    inner: F,
+    dir: PathBuf,
+    offline: bool,
+    default_max_age: Duration,
+    contexts: Arc<dyn DocumentLoader + Send + Sync>,
+}
+
+impl<F> CachingLookup<F> {
+    /// Cache the documents retrieved by `inner` in `dir`, creating it if needed.
+    pub fn new(inner: F, dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            inner,
+            dir,
+            offline: false,
+            default_max_age: Duration::from_secs(0),
+            contexts: Arc::new(ContextCache::new()),
+        })
+    }
+
+    /// Serve documents from the cache only.
+    pub fn offline(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
+    /// Consider documents without a `max-age` fresh for `max_age` after they were retrieved.
+    pub fn with_default_max_age(mut self, max_age: Duration) -> Self {
+        self.default_max_age = max_age;
+        self
+    }
+
+    /// Load the remote contexts of JSON-LD documents with `loader`.
+    pub fn with_context_loader(
+        mut self,
+        loader: impl DocumentLoader + Send + Sync + 'static,
+    ) -> Self {
+        self.contexts = Arc::new(loader);
+        self
+    }
+
+    fn path(&self, iri: &str) -> PathBuf {
+        let digest = Code::Sha2_256.digest(iri.as_bytes());
+        let name: String = digest
+            .digest()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        self.dir.join(name)
+    }
+
+    /// The cached copy of `iri`. Unreadable entries are treated as missing.
+    fn read(&self, iri: &str) -> Option<Entry> {
+        let file = fs::read(self.path(iri)).ok()?;
+        let split = file.iter().position(|b| *b == b'\n')?;
+        let meta: Meta = serde_json::from_slice(&file[..split]).ok()?;
+        if meta.iri != iri {
+            return None;
+        }
+        Some(Entry {
+            meta,
+            body: file[split + 1..].to_vec(),
+        })
+    }
+
+    /// Replace the cached copy of an iri, atomically so that concurrent readers never see half
+    /// an entry.
+    fn write(&self, entry: &Entry) -> io::Result<()> {
+        let path = self.path(&entry.meta.iri);
+        let tmp = path.with_extension(format!("{:016x}.tmp", rand::random::<u64>()));
+        let mut file = serde_json::to_vec(&entry.meta).expect("metadata is serializable");
+        file.push(b'\n');
+        file.extend_from_slice(&entry.body);
+        fs::write(&tmp, file)
+            .and_then(|()| fs::rename(&tmp, &path))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&tmp);
+            })
+    }
+
+    fn remove(&self, iri: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(iri)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn is_fresh(&self, meta: &Meta) -> bool {
+        let directives = CacheControl::parse(meta.cache_control.as_deref());
+        let max_age = directives.max_age.unwrap_or(self.default_max_age);
+        !directives.no_cache && now().saturating_sub(meta.validated) < max_age.as_secs()
+    }
+}
+
+#[async_trait]
+impl<F: Fetch + Send + Sync> Lookup for CachingLookup<F> {
+    async fn lookup(&self, iri: &om::NamedNode) -> Result<Graph, LookupError> {
+        let cached = self.read(iri.as_str());
+        let entry = match cached {
+            Some(entry) if self.offline || self.is_fresh(&entry.meta) => entry,
+            None if self.offline => return Err(LookupError::NotFound),
+            cached => {
+                let etag = cached.as_ref().and_then(|e| e.meta.etag.as_deref());
+                match (self.inner.fetch(iri, etag).await?, cached) {
+                    // Failing to update the cache only costs a fetch later, so the document is
+                    // returned regardless.
+                    (Fetched::Document(doc), _) => {
+                        let entry = Entry::new(iri.as_str(), doc);
+                        let _ = if CacheControl::parse(entry.meta.cache_control.as_deref()).no_store
+                        {
+                            self.remove(iri.as_str())
+                        } else {
+                            self.write(&entry)
+                        };
+                        entry
+                    }
+                    (Fetched::NotModified { cache_control }, Some(mut entry)) => {
+                        entry.meta.validated = now();
+                        if cache_control.is_some() {
+                            entry.meta.cache_control = cache_control;
+                        }
+                        let _ = self.write(&entry);
+                        entry
+                    }
+                    (Fetched::NotModified { .. }, None) => {
+                        return Err(LookupError::Transport(
+                            "document reported unmodified but there is no cached copy".into(),
+                        ))
+                    }
+                }
+            }
+        };
+        entry.into_document().parse(&*self.contexts).await
+    }
+}
+
+/// A cached document. On disk it is stored as its metadata, in JSON on a single line, followed
+/// by the raw body.
+struct Entry {
+    meta: Meta,
+    body: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Meta {
+    iri: String,
+    location: String,
+    content_type: Option<String>,
+    etag: Option<String>,
+    cache_control: Option<String>,
+    /// When the document was last retrieved or revalidated, in seconds since the unix epoch.
+    validated: u64,
+}
+
+impl Entry {
+    fn new(iri: &str, doc: RawDocument) -> Self {
+        let RawDocument {
+            location,
+            content_type,
+            body,
+            etag,
+            cache_control,
+        } = doc;
+        Entry {
+            meta: Meta {
+                iri: iri.to_string(),
+                location,
+                content_type,
+                etag,
+                cache_control,
+                validated: now(),
+            },
+            body,
+        }
+    }
+
+    fn into_document(self) -> RawDocument {
+        RawDocument {
+            location: self.meta.location,
+            content_type: self.meta.content_type,
+            body: self.body,
+            etag: self.meta.etag,
+            cache_control: self.meta.cache_control,
+        }
+    }
+}
+
+/// The `Cache-Control` directives that matter to a private cache.
+#[derive(Default)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    max_age: Option<Duration>,
+}
+
+impl CacheControl {
+    fn parse(header: Option<&str>) -> Self {
+        let mut ret = Self::default();
+        for directive in header.unwrap_or_default().split(',') {
+            let mut kv = directive.splitn(2, '=');
+            let key = kv.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let value = kv.next().map(|v| v.trim().trim_matches('"'));
+            match key.as_str() {
+                "no-store" => ret.no_store = true,
+                "no-cache" => ret.no_cache = true,
+                "max-age" => {
+                    // Unparsable ages are treated as already expired.
+                    let secs = value.and_then(|v| v.parse().ok()).unwrap_or(0);
+                    ret.max_age = Some(Duration::from_secs(secs));
+                }
+                _ => {}
+            }
+        }
+        ret
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fetch::LookupFetch;
+    use crate::http::HttpLookup;
+    use crate::router::RouterLookup;
+    use crate::test_server::{block_on, Response, TestServer};
+    use crate::ttl::from_ttl;
+    use alloc::collections::BTreeMap;
+
+    const TTL: &str = "<urn:a> <http://schema.org/name> \"a\" .";
+
+    #[test]
+    fn honours_cache_control() {
+        let server = TestServer::start(|req| {
+            let response = Response::ok("text/turtle", TTL);
+            match req.path.as_str() {
+                "/fresh" => response.header("Cache-Control", "public, max-age=3600"),
+                "/stale" => response.header("Cache-Control", "max-age=0"),
+                "/etag"
+                    if req.headers.get("if-none-match").map(String::as_str) == Some("\"1\"") =>
+                {
+                    Response::status(304).header("Cache-Control", "no-cache")
+                }
+                "/etag" => response
+                    .header("Cache-Control", "no-cache")
+                    .header("ETag", "\"1\""),
+                "/private" => response
+                    .header("Cache-Control", "no-store")
+                    .header("ETag", "\"2\""),
+                _ => Response::status(404),
+            }
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let cache = CachingLookup::new(HttpLookup::new(), dir.path()).unwrap();
+        let lookup = |path: &str| block_on(cache.lookup(&server.iri(path)));
+        let requests = || {
+            server
+                .requests()
+                .into_iter()
+                .map(|req| (req.path, req.headers.get("if-none-match").cloned()))
+                .collect::<Vec<_>>()
+        };
+        let paths = ["/fresh", "/stale", "/etag", "/private"];
+
+        for _ in 0..2 {
+            for path in &paths {
+                assert_eq!(lookup(path), Ok(from_ttl(TTL)), "{}", path);
+            }
+        }
+        assert_eq!(
+            requests(),
+            vec![
+                ("/fresh".into(), None),
+                ("/stale".into(), None),
+                ("/etag".into(), None),
+                ("/private".into(), None),
+                ("/stale".into(), None),
+                ("/etag".into(), Some("\"1\"".into())),
+                ("/private".into(), None),
+            ]
+        );
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn survives_unwritable_cache() {
+        let server = TestServer::start(|_| Response::ok("text/turtle", TTL));
+        let dir = tempfile::tempdir().unwrap();
+        let cache = CachingLookup::new(HttpLookup::new(), dir.path().join("cache")).unwrap();
+        fs::remove_dir(dir.path().join("cache")).unwrap();
+        for _ in 0..2 {
+            assert_eq!(
+                block_on(cache.lookup(&server.iri("/doc"))),
+                Ok(from_ttl(TTL))
+            );
+        }
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn replays_offline() {
+        let server = TestServer::start(|req| match req.path.as_str() {
+            "/doc" => Response::ok("text/turtle", "<a> <http://schema.org/name> \"a\" ."),
+            "/moved" => Response::redirect("/doc"),
+            _ => Response::status(404),
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let expected = Ok(from_ttl(&format!(
+            "<{}> <http://schema.org/name> \"a\" .",
+            server.url("/a")
+        )));
+
+        let online = CachingLookup::new(HttpLookup::new(), dir.path()).unwrap();
+        assert_eq!(block_on(online.lookup(&server.iri("/moved"))), expected);
+        assert_eq!(
+            block_on(online.lookup(&server.iri("/missing"))),
+            Err(LookupError::NotFound)
+        );
+        server.requests();
+
+        let offline = CachingLookup::new(HttpLookup::new(), dir.path())
+            .unwrap()
+            .offline();
+        assert_eq!(block_on(offline.lookup(&server.iri("/moved"))), expected);
+        assert_eq!(
+            block_on(offline.lookup(&server.iri("/doc"))),
+            Err(LookupError::NotFound)
+        );
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn caches_any_lookup() {
+        let ttl = "<did:a> <http://schema.org/name> \"say \\\"hi\\\"\\n\"@en .";
+        let documents: BTreeMap<&str, Graph> = [("did:a", from_ttl(ttl))].iter().cloned().collect();
+        let router = RouterLookup::new().with_scheme("did", documents);
+        let dir = tempfile::tempdir().unwrap();
+        let a = om::NamedNode::new_unchecked("did:a");
+
+        let online = CachingLookup::new(LookupFetch(router), dir.path()).unwrap();
+        assert_eq!(block_on(online.lookup(&a)), Ok(from_ttl(ttl)));
+        let offline = CachingLookup::new(LookupFetch(RouterLookup::new()), dir.path())
+            .unwrap()
+            .offline();
+        assert_eq!(block_on(offline.lookup(&a)), Ok(from_ttl(ttl)));
+    }
+}
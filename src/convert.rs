@@ -37,20 +37,32 @@ impl AsBlank for NamedOrBlankNode {
     }
 }
 
-#[cfg(test)]
 /// rio_api_model to oxigraph model
 pub mod rm_to_om {
+    use core::fmt;
     use oxigraph::model as om;
     use rio_api::model as rm;
     use tap::pipe::Pipe as _;
 
     #[derive(Debug)]
     pub enum ConvertErr {
-        BadIri(om::IriParseError),
-        BadBlank(om::BlankNodeIdParseError),
-        BadTag(om::LanguageTagParseError),
+        Iri(om::IriParseError),
+        Blank(om::BlankNodeIdParseError),
+        Tag(om::LanguageTagParseError),
     }
 
+    impl fmt::Display for ConvertErr {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                ConvertErr::Iri(e) => write!(f, "invalid iri: {}", e),
+                ConvertErr::Blank(e) => write!(f, "invalid blank node id: {}", e),
+                ConvertErr::Tag(e) => write!(f, "invalid language tag: {}", e),
+            }
+        }
+    }
+
+    impl std::error::Error for ConvertErr {}
+
     pub fn triple(tr: rm::Triple) -> Result<om::Triple, ConvertErr> {
         let rm::Triple {
             subject,
@@ -105,19 +117,19 @@ pub mod rm_to_om {
 
     impl From<om::IriParseError> for ConvertErr {
         fn from(other: om::IriParseError) -> Self {
-            ConvertErr::BadIri(other)
+            ConvertErr::Iri(other)
         }
     }
 
     impl From<om::BlankNodeIdParseError> for ConvertErr {
         fn from(other: om::BlankNodeIdParseError) -> Self {
-            ConvertErr::BadBlank(other)
+            ConvertErr::Blank(other)
         }
     }
 
     impl From<om::LanguageTagParseError> for ConvertErr {
         fn from(other: om::LanguageTagParseError) -> Self {
-            ConvertErr::BadTag(other)
+            ConvertErr::Tag(other)
         }
     }
 }
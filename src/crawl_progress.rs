@@ -1,25 +1,138 @@
 //! Tracks the progress of a crawl
+//!
+//! Progress is serializable so that a long crawl can be saved and later resumed with
+//! [`Agent::resume`](crate::Agent::resume).
 
-use alloc::collections::BTreeSet;
+use crate::frontier::Frontier;
+use crate::lookup::LookupError;
+use crate::provenance::Provenance;
+use alloc::collections::{BTreeMap, BTreeSet};
 use oxigraph::model as om;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CrawlProgress {
     visited: BTreeSet<String>,
-    errors: BTreeSet<String>,
+    /// When each visited document was last looked up successfully. Snapshots taken before this
+    /// was recorded lack it, so their documents are considered arbitrarily old.
+    #[serde(default)]
+    fetched: BTreeMap<String, SystemTime>,
+    errors: BTreeMap<String, Failure>,
+    /// Documents the agent has decided to look up but has not yet.
+    frontier: Frontier,
+    /// Why the agent first became curious about each document.
+    #[serde(default)]
+    provenance: BTreeMap<String, Provenance>,
+}
+
+/// Why, how often and when looking up a document failed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Failure {
+    /// The error from the most recent attempt.
+    pub error: LookupError,
+    pub attempts: u32,
+    pub first_failed: SystemTime,
+    pub last_failed: SystemTime,
 }
 
 impl CrawlProgress {
     pub fn novel(&self, iri: &om::NamedNode) -> bool {
         let st = iri.as_str();
-        !(self.visited.contains(st) || self.errors.contains(st))
+        !(self.visited.contains(st) || self.errors.contains_key(st))
     }
 
     pub fn visit(&mut self, iri: om::NamedNode) {
-        self.visited.insert(iri.into_string());
+        let iri = iri.into_string();
+        self.errors.remove(&iri);
+        self.frontier.remove(&iri);
+        self.fetched.insert(iri.clone(), SystemTime::now());
+        self.visited.insert(iri);
+    }
+
+    /// Whether `iri` has been looked up successfully.
+    pub fn visited(&self, iri: &om::NamedNode) -> bool {
+        self.visited.contains(iri.as_str())
+    }
+
+    /// When `iri` was last looked up successfully, if it is known.
+    pub fn fetched_at(&self, iri: &om::NamedNode) -> Option<SystemTime> {
+        self.fetched.get(iri.as_str()).copied()
+    }
+
+    /// Visited documents along with when they become older than `max_age`, whether or not that
+    /// has happened yet. Documents whose latest lookup failed are first left to the retry policy,
+    /// and become due again `max_age` after the last failure. Documents that would never become
+    /// that old are omitted.
+    pub fn expiries(&self, max_age: Duration) -> impl Iterator<Item = (&str, SystemTime)> {
+        self.visited.iter().filter_map(move |iri| {
+            let fetched = self.fetched.get(iri).copied().unwrap_or(UNIX_EPOCH);
+            let since = match self.errors.get(iri) {
+                Some(failure) => failure.last_failed.max(fetched),
+                None => fetched,
+            };
+            Some((iri.as_str(), since.checked_add(max_age)?))
+        })
+    }
+
+    pub fn error(&mut self, iri: om::NamedNode, error: LookupError) {
+        let now = SystemTime::now();
+        self.frontier.remove(iri.as_str());
+        match self.errors.get_mut(iri.as_str()) {
+            Some(failure) => {
+                failure.error = error;
+                failure.attempts += 1;
+                failure.last_failed = now;
+            }
+            None => {
+                let failure = Failure {
+                    error,
+                    attempts: 1,
+                    first_failed: now,
+                    last_failed: now,
+                };
+                self.errors.insert(iri.into_string(), failure);
+            }
+        }
+    }
+
+    /// Note that `iri` should be looked up. Documents with higher priority are looked up first.
+    pub fn enqueue(&mut self, iri: om::NamedNode, priority: f64) {
+        self.frontier.push(iri.into_string(), priority);
+    }
+
+    /// Note why the agent became curious about `iri`. Only the first explanation is kept.
+    pub fn note_provenance(&mut self, iri: &om::NamedNode, provenance: Provenance) {
+        self.provenance
+            .entry(iri.as_str().to_string())
+            .or_insert(provenance);
+    }
+
+    /// Why the agent first became curious about `iri`, if it was found by a curiosity query.
+    pub fn explain(&self, iri: &om::NamedNode) -> Option<&Provenance> {
+        self.provenance.get(iri.as_str())
+    }
+
+    /// Take the most interesting document off the frontier.
+    pub fn dequeue(&mut self) -> Option<om::NamedNode> {
+        self.frontier
+            .pop()
+            .map(|(iri, _priority)| om::NamedNode::new_unchecked(iri))
+    }
+
+    /// Documents waiting to be looked up along with their priorities, most interesting first.
+    pub fn frontier(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.frontier.iter()
+    }
+
+    /// Every document that could not be looked up, ordered by iri.
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &Failure)> {
+        self.errors
+            .iter()
+            .map(|(iri, failure)| (iri.as_str(), failure))
     }
 
-    pub fn error(&mut self, iri: om::NamedNode) {
-        self.errors.insert(iri.into_string());
+    pub fn failure(&self, iri: &om::NamedNode) -> Option<&Failure> {
+        self.errors.get(iri.as_str())
     }
 }
@@ -8,11 +8,14 @@ use ipfs_api::IpfsClient;
 #[async_trait]
 impl Resolve for IpfsClient {
     async fn lookup(&mut self, iri: &str) -> Result<Vec<u8>, ResolveError> {
-        self.cat(iri.strip_prefix("ipfs://ipfs/").ok_or("bad prefix")?)
+        let path = iri
+            .strip_prefix("ipfs://ipfs/")
+            .ok_or_else(|| ResolveError::Refused("not an ipfs://ipfs/ iri".to_string()))?;
+        self.cat(path)
             .map_ok(|a| a.to_vec())
             .try_concat()
             .await
-            .map_err(Into::into)
+            .map_err(|e| ResolveError::Transport(e.to_string()))
     }
 }
 
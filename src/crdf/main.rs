@@ -5,11 +5,10 @@ extern crate alloc;
 extern crate core;
 
 mod ipfs_client_resolver;
-mod parse;
 mod resolve;
-mod rm_to_om;
 
 use ipfs_api::IpfsClient;
+use quaerit_machina::parse_document;
 use resolve::Resolve;
 use structopt::StructOpt;
 
@@ -24,7 +23,7 @@ async fn main() {
     let mut resolver = IpfsClient::default();
     for iri in Args::from_args().iri {
         let doc = resolver.lookup(&iri).await.unwrap();
-        let graph = parse::into_rdf(&doc, "text/turtle; charset=utf-8").unwrap();
+        let graph = parse_document(&doc, Some("text/turtle"), Some(&iri)).unwrap();
         dbg!(graph);
     }
 }
@@ -1,17 +1,9 @@
 //! The Resolve trait specifies the ability to find some document addressed by iri
 
 use async_trait::async_trait;
-use core::fmt::{Debug, Display};
 
-/// stringly typed error
-#[derive(Debug)]
-pub struct ResolveError(String);
-
-impl<T: Display> From<T> for ResolveError {
-    fn from(other: T) -> ResolveError {
-        ResolveError(format!("{}", other))
-    }
-}
+/// Resolution fails for the same reasons a library lookup does.
+pub use quaerit_machina::LookupError as ResolveError;
 
 #[async_trait]
 pub trait Resolve {
@@ -1,48 +1,492 @@
+//! Curiosity is a set of SPARQL queries over the knowledge graph whose results name the
+//! documents an agent should look up next.
+//!
+//! - SELECT queries: terms bound to the query's document variables are candidate documents. See
+//!   [`CuriousQuery::documents`].
+//! - CONSTRUCT queries: the subject of every constructed triple with the predicate
+//!   [`SHOULD_FETCH`] is a candidate document.
+//! - ASK queries are preconditions. While any of them answers false the agent is not curious
+//!   about anything.
+
+use crate::provenance::{Evidence, Provenance};
 use crate::store::Store;
+use core::fmt;
 use oxigraph::model as om;
-use oxigraph::sparql::{algebra::Query, EvaluationError, QueryResults};
+use oxigraph::sparql::algebra::{Expression, GraphPattern, NamedNodeOrVariable};
+use oxigraph::sparql::{algebra::Query, EvaluationError, QueryResults, Variable};
+
+/// Predicate used by CONSTRUCT curiosity queries to name a document to look up, as in
+/// `CONSTRUCT { ?doc dock:shouldFetch true } WHERE { ... }`. A numeric object is used as the
+/// document's `?priority`.
+pub const SHOULD_FETCH: &str = "https://dock.io/rdf/alpha/shouldFetch";
 
 pub struct Curiosity {
-    /// queries in this list must all be select statements
-    curiosity: Vec<Query>,
+    /// ASK queries, all of which must hold for the agent to be curious
+    preconditions: Vec<Query>,
+    /// SELECT and CONSTRUCT queries
+    curiosity: Vec<CuriousQuery>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CuriosityError {
+    /// DESCRIBE queries can't be used for curiosity. `index` is the position of the offending
+    /// query in the list passed to [`Curiosity::create`].
+    Describe { index: usize },
+    /// A SELECT query was told its documents are bound to a variable it does not project.
+    UnknownVariable { index: usize, variable: String },
+}
+
+impl fmt::Display for CuriosityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CuriosityError::Describe { index } => write!(
+                f,
+                "curiosity query {} is a DESCRIBE query, which is not supported",
+                index
+            ),
+            CuriosityError::UnknownVariable { index, variable } => write!(
+                f,
+                "curiosity query {} does not select the document variable ?{}",
+                index, variable
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CuriosityError {}
+
+/// A curiosity query along with how interesting its results are.
+///
+/// The priority of a document found by the query is the query's weight, multiplied by the
+/// numeric value bound to `?priority` in the same solution if there is one. `?priority` is never
+/// itself treated as a document.
+#[derive(Clone, Debug)]
+pub struct CuriousQuery {
+    query: Query,
+    weight: f64,
+    documents: Option<Vec<Variable>>,
+    name: Option<String>,
+    /// Position in the list passed to [`Curiosity::create`].
+    index: usize,
+}
+
+impl CuriousQuery {
+    pub fn new(query: Query) -> Self {
+        Self {
+            query,
+            weight: 1.0,
+            documents: None,
+            name: None,
+            index: 0,
+        }
+    }
+
+    /// A human readable name for the query, recorded in the provenance of the documents it finds.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn weight(mut self, weight: f64) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Name the variables of a SELECT query that are bound to documents; other variables are
+    /// ignored. Names are given without the leading `?`.
+    ///
+    /// When this is not called, a query that selects `?doc` is assumed to bind documents to
+    /// `?doc` only. Otherwise every variable except `?priority` is assumed to bind documents.
+    pub fn documents(mut self, variables: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.documents = Some(
+            variables
+                .into_iter()
+                .map(|v| Variable::new_unchecked(v))
+                .collect(),
+        );
+        self
+    }
+
+    /// The GRAPH pattern solutions are drawn from. If there are several, the first.
+    fn source_graph(&self) -> Option<&NamedNodeOrVariable> {
+        match &self.query {
+            Query::Select { pattern, .. } | Query::Construct { pattern, .. } => graph_name(pattern),
+            Query::Ask { .. } | Query::Describe { .. } => None,
+        }
+    }
+
+    fn provenance(&self, graph: Option<String>, evidence: Evidence) -> Provenance {
+        Provenance {
+            query: self.index,
+            query_name: self.name.clone(),
+            graph,
+            evidence,
+        }
+    }
+
+    /// The variables holding documents in a solution that selects `selected`.
+    fn document_variables(&self, selected: &[Variable]) -> Vec<Variable> {
+        if let Some(documents) = &self.documents {
+            return documents.clone();
+        }
+        if selected.iter().any(|v| v.as_str() == DOC) {
+            return vec![Variable::new_unchecked(DOC)];
+        }
+        selected
+            .iter()
+            .filter(|v| v.as_str() != PRIORITY)
+            .cloned()
+            .collect()
+    }
 }
 
+impl From<Query> for CuriousQuery {
+    fn from(query: Query) -> Self {
+        Self::new(query)
+    }
+}
+
+/// The variable a curiosity query may bind to make individual solutions more or less interesting.
+const PRIORITY: &str = "priority";
+
+/// By convention, the variable a SELECT curiosity query binds documents to.
+const DOC: &str = "doc";
+
 impl Curiosity {
-    pub fn create(curiosity: Vec<Query>) -> Result<Self, ()> {
-        if !curiosity.iter().all(is_select) {
-            return Err(());
+    pub fn create(
+        curiosity: impl IntoIterator<Item = impl Into<CuriousQuery>>,
+    ) -> Result<Self, CuriosityError> {
+        let mut ret = Self {
+            preconditions: Vec::new(),
+            curiosity: Vec::new(),
+        };
+        for (index, cq) in curiosity.into_iter().map(Into::into).enumerate() {
+            let cq = CuriousQuery { index, ..cq };
+            match &cq.query {
+                Query::Ask { .. } => ret.preconditions.push(cq.query),
+                Query::Select { pattern, .. } => {
+                    if let (Some(documents), Some(selected)) = (&cq.documents, projection(pattern))
+                    {
+                        if let Some(unknown) = documents.iter().find(|v| !selected.contains(v)) {
+                            return Err(CuriosityError::UnknownVariable {
+                                index,
+                                variable: unknown.as_str().to_string(),
+                            });
+                        }
+                    }
+                    ret.curiosity.push(cq)
+                }
+                Query::Construct { .. } => ret.curiosity.push(cq),
+                Query::Describe { .. } => return Err(CuriosityError::Describe { index }),
+            }
         }
-        Ok(Self { curiosity })
+        Ok(ret)
     }
 
+    /// Report every document named by one of the curiosity queries, along with its priority and
+    /// a way to explain why it was named.
+    ///
+    /// When `fresh` is `Some`, the caller has already been told about every solution found in
+    /// named graphs other than those listed, so queries that can be evaluated incrementally are
+    /// restricted to the listed graphs. Queries that can't be are evaluated against the whole
+    /// store, as are queries reading the default graph of a store that doesn't
+    /// [keep it](Store::keeps_default_graph) when the named graphs are restricted.
+    ///
+    /// Returns false, without evaluating any curiosity query, if a precondition does not hold.
+    /// The caller has then not been told about the solutions in `fresh`.
     pub fn curious(
         &self,
         store: &impl Store,
-        mut interesting: impl FnMut(&om::Term),
-    ) -> Result<(), EvaluationError> {
-        for cur in &self.curiosity {
-            let q = store.query(cur.clone())?;
-            match q {
+        fresh: Option<&[om::NamedOrBlankNode]>,
+        mut interesting: impl FnMut(&om::Term, f64, &dyn Fn() -> Provenance),
+    ) -> Result<bool, EvaluationError> {
+        for pre in &self.preconditions {
+            if let QueryResults::Boolean(false) = store.query(pre.clone())? {
+                return Ok(false);
+            }
+        }
+        for cq in &self.curiosity {
+            let weight = cq.weight;
+            let mut cur = cq.query.clone();
+            match fresh {
+                Some(fresh)
+                    if is_incremental(&cur)
+                        && (store.keeps_default_graph() || !reads_default_graph(&cur)) =>
+                {
+                    if fresh.is_empty() {
+                        continue;
+                    }
+                    cur.dataset_mut().set_available_named_graphs(fresh.to_vec());
+                }
+                _ => {}
+            }
+            match store.query(cur)? {
                 QueryResults::Solutions(solutions) => {
+                    let documents = cq.document_variables(solutions.variables());
                     for s in solutions {
-                        for (_name, term) in s?.iter() {
-                            interesting(term);
+                        let s = s?;
+                        let priority = weight * s.get(PRIORITY).and_then(numeric).unwrap_or(1.0);
+                        let explain = || {
+                            let graph = match cq.source_graph() {
+                                Some(NamedNodeOrVariable::NamedNode(nn)) => Some(nn.to_string()),
+                                Some(NamedNodeOrVariable::Variable(v)) => {
+                                    s.get(v).map(ToString::to_string)
+                                }
+                                None => None,
+                            };
+                            let solution = s
+                                .iter()
+                                .map(|(v, t)| (v.as_str().to_string(), t.to_string()))
+                                .collect();
+                            cq.provenance(graph, Evidence::Solution(solution))
+                        };
+                        for term in documents.iter().filter_map(|v| s.get(v)) {
+                            interesting(term, priority, &explain);
                         }
                     }
                 }
-                QueryResults::Boolean(_) | QueryResults::Graph(_) => {
-                    panic!("Expected SELECT statements only.");
+                QueryResults::Graph(triples) => {
+                    for t in triples {
+                        let t = t?;
+                        if t.predicate.as_str() != SHOULD_FETCH {
+                            continue;
+                        }
+                        let priority = weight * numeric(&t.object).unwrap_or(1.0);
+                        let explain = || {
+                            let graph = match cq.source_graph() {
+                                Some(NamedNodeOrVariable::NamedNode(nn)) => Some(nn.to_string()),
+                                Some(NamedNodeOrVariable::Variable(_)) | None => None,
+                            };
+                            let evidence = Evidence::Constructed {
+                                subject: t.subject.to_string(),
+                                predicate: t.predicate.to_string(),
+                                object: t.object.to_string(),
+                            };
+                            cq.provenance(graph, evidence)
+                        };
+                        interesting(&t.subject.clone().into(), priority, &explain);
+                    }
                 }
+                QueryResults::Boolean(_) => {}
             }
         }
-        Ok(())
+        Ok(true)
+    }
+}
+
+fn numeric(term: &om::Term) -> Option<f64> {
+    match term {
+        om::Term::Literal(lit) => lit.value().parse().ok(),
+        om::Term::NamedNode(_) | om::Term::BlankNode(_) => None,
+    }
+}
+
+/// The variables selected by a SELECT query's pattern, if they can be determined statically.
+fn projection(pattern: &GraphPattern) -> Option<&[Variable]> {
+    match pattern {
+        GraphPattern::Project { projection, .. } => Some(projection),
+        GraphPattern::Distinct { inner }
+        | GraphPattern::Reduced { inner }
+        | GraphPattern::Slice { inner, .. }
+        | GraphPattern::OrderBy { inner, .. } => projection(inner),
+        _ => None,
     }
 }
 
-fn is_select(q: &Query) -> bool {
+fn graph_name(pattern: &GraphPattern) -> Option<&NamedNodeOrVariable> {
+    use GraphPattern::*;
+    match pattern {
+        BGP(_) | Path { .. } | Table { .. } => None,
+        Join { left, right }
+        | LeftJoin { left, right, .. }
+        | Union { left, right }
+        | Minus { left, right } => graph_name(left).or_else(|| graph_name(right)),
+        Graph { graph_name, .. } => Some(graph_name),
+        Filter { inner, .. }
+        | Extend { inner, .. }
+        | OrderBy { inner, .. }
+        | Project { inner, .. }
+        | Distinct { inner }
+        | Reduced { inner }
+        | Slice { inner, .. }
+        | Group { inner, .. } => graph_name(inner),
+        Service { .. } => None,
+    }
+}
+
+/// Whether restricting the named graphs a query may read to some subset finds exactly those
+/// solutions which involve that subset. This holds when the query reads from a single GRAPH
+/// pattern per union branch and does not aggregate, slice or test for existence.
+fn is_incremental(q: &Query) -> bool {
+    match q {
+        Query::Select {
+            dataset, pattern, ..
+        }
+        | Query::Construct {
+            dataset, pattern, ..
+        } => dataset.is_default_dataset() && graph_patterns(pattern) == Some(1),
+        Query::Ask { .. } | Query::Describe { .. } => false,
+    }
+}
+
+/// Whether any part of `q` is matched against the default graph.
+fn reads_default_graph(q: &Query) -> bool {
     match q {
-        Query::Select { .. } => true,
-        _ => false,
+        Query::Select { pattern, .. } | Query::Construct { pattern, .. } => {
+            pattern_reads_default_graph(pattern)
+        }
+        Query::Ask { .. } | Query::Describe { .. } => true,
+    }
+}
+
+fn pattern_reads_default_graph(pattern: &GraphPattern) -> bool {
+    use GraphPattern::*;
+    match pattern {
+        BGP(triples) => !triples.is_empty(),
+        Path { .. } => true,
+        Graph { .. } | Table { .. } | Service { .. } => false,
+        Join { left, right }
+        | LeftJoin { left, right, .. }
+        | Union { left, right }
+        | Minus { left, right } => {
+            pattern_reads_default_graph(left) || pattern_reads_default_graph(right)
+        }
+        Filter { inner, .. }
+        | Extend { inner, .. }
+        | OrderBy { inner, .. }
+        | Project { inner, .. }
+        | Distinct { inner }
+        | Reduced { inner }
+        | Slice { inner, .. }
+        | Group { inner, .. } => pattern_reads_default_graph(inner),
+    }
+}
+
+/// The number of GRAPH patterns a solution to `pattern` draws on, or None if `pattern` can't be
+/// evaluated incrementally.
+fn graph_patterns(pattern: &GraphPattern) -> Option<usize> {
+    use GraphPattern::*;
+    match pattern {
+        BGP(_) | Path { .. } | Table { .. } => Some(0),
+        Join { left, right } => Some(graph_patterns(left)? + graph_patterns(right)?),
+        Union { left, right } => Some(graph_patterns(left)?.max(graph_patterns(right)?)),
+        LeftJoin { left, right, expr } => {
+            if matches!(expr, Some(expr) if tests_existence(expr)) {
+                return None;
+            }
+            Some(graph_patterns(left)? + graph_patterns(right)?)
+        }
+        Filter { expr, inner } | Extend { expr, inner, .. } => {
+            if tests_existence(expr) {
+                return None;
+            }
+            graph_patterns(inner)
+        }
+        Graph { inner, .. } => Some(graph_patterns(inner)? + 1),
+        OrderBy { inner, .. } | Project { inner, .. } | Distinct { inner } | Reduced { inner } => {
+            graph_patterns(inner)
+        }
+        Minus { .. } | Slice { .. } | Group { .. } | Service { .. } => None,
     }
 }
 
+/// Whether `expr` uses EXISTS or NOT EXISTS, which may look at graphs outside the restricted set.
+fn tests_existence(expr: &Expression) -> bool {
+    use Expression::*;
+    match expr {
+        Exists(_) => true,
+        NamedNode(_) | Literal(_) | Variable(_) | Bound(_) => false,
+        UnaryPlus(e) | UnaryMinus(e) | Not(e) => tests_existence(e),
+        Or(a, b)
+        | And(a, b)
+        | Equal(a, b)
+        | SameTerm(a, b)
+        | Greater(a, b)
+        | GreaterOrEqual(a, b)
+        | Less(a, b)
+        | LessOrEqual(a, b)
+        | Add(a, b)
+        | Subtract(a, b)
+        | Multiply(a, b)
+        | Divide(a, b) => tests_existence(a) || tests_existence(b),
+        If(a, b, c) => tests_existence(a) || tests_existence(b) || tests_existence(c),
+        In(a, list) => tests_existence(a) || list.iter().any(tests_existence),
+        Coalesce(list) | FunctionCall(_, list) => list.iter().any(tests_existence),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn incremental() {
+        for (query, expected) in &[
+            ("SELECT ?s WHERE { GRAPH ?g { ?s ?p ?o } }", true),
+            ("SELECT DISTINCT ?g WHERE { GRAPH ?g { ?s ?p ?o } }", true),
+            (
+                "SELECT ?s WHERE { { GRAPH ?g { ?s ?p ?o } } UNION { GRAPH ?g { ?o ?p ?s } } }",
+                true,
+            ),
+            ("SELECT ?s WHERE { ?s ?p ?o }", false),
+            (
+                "SELECT ?s WHERE { GRAPH ?g { ?s ?p ?o } GRAPH ?h { ?o ?p ?s } }",
+                false,
+            ),
+            ("SELECT ?s WHERE { GRAPH ?g { ?s ?p ?o } } LIMIT 10", false),
+            (
+                "SELECT ?s (COUNT(?o) AS ?c) WHERE { GRAPH ?g { ?s ?p ?o } } GROUP BY ?s",
+                false,
+            ),
+            (
+                "SELECT ?s WHERE { GRAPH ?g { ?s ?p ?o } FILTER NOT EXISTS { ?o ?p ?s } }",
+                false,
+            ),
+            (
+                "SELECT ?s WHERE { GRAPH ?g { ?s ?p ?o } FILTER(?o = 1 || EXISTS { ?o ?p ?s }) }",
+                false,
+            ),
+            (
+                "SELECT ?s ?x WHERE { GRAPH ?g { ?s ?p ?o } BIND(IF(EXISTS { ?o ?p ?s }, 1, 0) AS ?x) }",
+                false,
+            ),
+            (
+                "SELECT ?s WHERE { GRAPH ?g { ?s ?p ?o } FILTER(STR(?o) != \"(exists \") }",
+                true,
+            ),
+            (
+                "SELECT ?s FROM NAMED <did:a> WHERE { GRAPH ?g { ?s ?p ?o } }",
+                false,
+            ),
+        ] {
+            let q: Query = query.parse().unwrap();
+            assert_eq!(is_incremental(&q), *expected, "{}", query);
+        }
+    }
+
+    #[test]
+    fn default_graph() {
+        for (query, expected) in &[
+            ("SELECT ?s WHERE { GRAPH ?g { ?s ?p ?o } }", false),
+            (
+                "SELECT ?o WHERE { <urn:a> <urn:b> ?p . GRAPH ?g { ?s ?p ?o } }",
+                true,
+            ),
+            (
+                "SELECT ?o WHERE { GRAPH ?g { ?s ?p ?o } OPTIONAL { ?o <urn:b>+ ?x } }",
+                true,
+            ),
+            (
+                "SELECT ?s WHERE { GRAPH ?g { ?s ?p ?o } VALUES ?p { <urn:b> } }",
+                false,
+            ),
+            (
+                "CONSTRUCT { ?s <urn:b> ?o } WHERE { { GRAPH ?g { ?s ?p ?o } } UNION { ?s ?p ?o } }",
+                true,
+            ),
+        ] {
+            let q: Query = query.parse().unwrap();
+            assert_eq!(reads_default_graph(&q), *expected, "{}", query);
+        }
+    }
+}
@@ -0,0 +1,441 @@
+//! Resolving DIDs to their DID documents.
+
+use crate::http::HttpLookup;
+use crate::jsonld::ContextCache;
+use crate::lookup::{Lookup, LookupError};
+use crate::rdf_graph::Graph;
+use async_trait::async_trait;
+use multibase::Base;
+use oxigraph::model as om;
+use serde_json::{json, Value as Json};
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const SEC: &str = "https://w3id.org/security#";
+
+/// The contexts served by [`ContextCache::with_did_contexts`].
+const DID_V1: &str = "https://www.w3.org/ns/did/v1";
+const ED25519_2020_V1: &str = "https://w3id.org/security/suites/ed25519-2020/v1";
+const X25519_2020_V1: &str = "https://w3id.org/security/suites/x25519-2020/v1";
+const JWS_2020_V1: &str = "https://w3id.org/security/suites/jws-2020/v1";
+
+/// Multicodec codes of the public keys did:key can encode.
+const ED25519_PUB: u64 = 0xed;
+const X25519_PUB: u64 = 0xec;
+
+/// Resolves `did:key` and `did:web` DIDs.
+///
+/// did:key documents are computed from the DID itself, so they are always available. Ed25519
+/// and X25519 keys are supported. The X25519 key agreement key that may be derived from an
+/// Ed25519 key is left out of the document.
+///
+/// did:web documents are retrieved as `did.json` over https by another lookup, [`HttpLookup`]
+/// unless told otherwise. DID documents are JSON-LD, so that lookup needs to be able to load the
+/// contexts they refer to. The default one serves the DID core context and the contexts of the
+/// common verification method suites from memory, see [`ContextCache::with_did_contexts`].
+pub struct DidLookup<L = HttpLookup> {
+    web: L,
+}
+
+impl DidLookup {
+    pub fn new() -> Self {
+        Self {
+            web: HttpLookup::new().with_context_loader(ContextCache::new().with_did_contexts()),
+        }
+    }
+}
+
+impl Default for DidLookup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L> DidLookup<L> {
+    /// Retrieve did:web documents with `web`. It will be asked for `https` iris.
+    pub fn with_web_lookup<M>(self, web: M) -> DidLookup<M> {
+        DidLookup { web }
+    }
+}
+
+#[async_trait]
+impl<L: Lookup + Send + Sync> Lookup for DidLookup<L> {
+    async fn lookup(&self, iri: &om::NamedNode) -> Result<Graph, LookupError> {
+        let did = iri.as_str().split('#').next().unwrap_or_default();
+        if let Some(key) = did.strip_prefix("did:key:") {
+            did_key(did, key)
+        } else if let Some(domain) = did.strip_prefix("did:web:") {
+            let url = did_web_url(domain)
+                .and_then(|url| om::NamedNode::new(url).ok())
+                .ok_or_else(|| invalid(did))?;
+            self.web.lookup(&url).await
+        } else {
+            Err(LookupError::Refused(format!(
+                "{} is not a did:key or did:web DID",
+                did
+            )))
+        }
+    }
+}
+
+/// The DID document of `did:key:<key>`.
+fn did_key(did: &str, key: &str) -> Result<Graph, LookupError> {
+    let (base, bytes) = multibase::decode(key).map_err(|_| invalid(did))?;
+    if base != Base::Base58Btc {
+        return Err(invalid(did));
+    }
+    let (codec, public_key) = varint(&bytes).ok_or_else(|| invalid(did))?;
+    let (key_type, relationships): (_, &[_]) = match codec {
+        ED25519_PUB => (
+            "Ed25519VerificationKey2020",
+            &[
+                "authenticationMethod",
+                "assertionMethod",
+                "capabilityDelegationMethod",
+                "capabilityInvocationMethod",
+            ],
+        ),
+        X25519_PUB => ("X25519KeyAgreementKey2020", &["keyAgreementMethod"]),
+        _ => {
+            return Err(LookupError::UnsupportedContentType(format!(
+                "did:key with multicodec {:#x}",
+                codec
+            )))
+        }
+    };
+    if public_key.len() != 32 {
+        return Err(invalid(did));
+    }
+
+    let subject = om::NamedNode::new(did).map_err(|_| invalid(did))?;
+    let method = om::NamedNode::new(format!("{}#{}", did, key)).map_err(|_| invalid(did))?;
+    let mut triples = vec![
+        triple(&subject, &sec("verificationMethod"), method.clone()),
+        triple(
+            &method,
+            &om::NamedNode::new_unchecked(RDF_TYPE),
+            sec(key_type),
+        ),
+        triple(&method, &sec("controller"), subject.clone()),
+        triple(
+            &method,
+            &sec("publicKeyMultibase"),
+            om::Literal::new_typed_literal(key, sec("multibase")),
+        ),
+    ];
+    for relationship in relationships {
+        triples.push(triple(&subject, &sec(relationship), method.clone()));
+    }
+    Ok(Graph::new(triples.into_iter()))
+}
+
+/// The url of the DID document for `did:web:<domain>`, or None if `domain` is not validly
+/// percent-encoded.
+fn did_web_url(domain: &str) -> Option<String> {
+    let segments = domain
+        .split(':')
+        .map(percent_decode)
+        .collect::<Option<Vec<_>>>()?;
+    let (host, path) = segments.split_first()?;
+    Some(if path.is_empty() {
+        format!("https://{}/.well-known/did.json", host)
+    } else {
+        format!("https://{}/{}/did.json", host, path.join("/"))
+    })
+}
+
+/// Decode the `%XX` escapes in one segment of a did:web method specific id.
+fn percent_decode(segment: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = core::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Split an unsigned varint off the front of `bytes`.
+fn varint(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    let mut ret = 0;
+    for (i, byte) in bytes.iter().enumerate().take(9) {
+        ret |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((ret, &bytes[i + 1..]));
+        }
+    }
+    None
+}
+
+fn triple(
+    subject: &om::NamedNode,
+    predicate: &om::NamedNode,
+    object: impl Into<om::Term>,
+) -> om::Triple {
+    om::Triple::new(subject.clone(), predicate.clone(), object)
+}
+
+fn sec(suffix: &str) -> om::NamedNode {
+    om::NamedNode::new_unchecked(sec_iri(suffix))
+}
+
+fn invalid(did: &str) -> LookupError {
+    LookupError::Refused(format!("{} is not a valid DID", did))
+}
+
+impl ContextCache {
+    /// Serve the DID core context and the contexts of the Ed25519, X25519 and JSON web key
+    /// verification method suites, which DID documents usually refer to.
+    pub fn with_did_contexts(self) -> Self {
+        let multibase =
+            json!({"@id": sec_iri("publicKeyMultibase"), "@type": sec_iri("multibase")});
+        let jwk = json!({"@id": sec_iri("publicKeyJwk"), "@type": "@json"});
+        self.with_context(DID_V1, did_v1())
+            .with_context(
+                ED25519_2020_V1,
+                suite(
+                    "Ed25519VerificationKey2020",
+                    ("publicKeyMultibase", multibase.clone()),
+                    Some("Ed25519Signature2020"),
+                ),
+            )
+            .with_context(
+                X25519_2020_V1,
+                suite(
+                    "X25519KeyAgreementKey2020",
+                    ("publicKeyMultibase", multibase),
+                    None,
+                ),
+            )
+            .with_context(
+                JWS_2020_V1,
+                suite(
+                    "JsonWebKey2020",
+                    ("publicKeyJwk", jwk),
+                    Some("JsonWebSignature2020"),
+                ),
+            )
+    }
+}
+
+/// The DID core context, `https://www.w3.org/ns/did/v1`.
+fn did_v1() -> Json {
+    let relationship =
+        |suffix: &str| json!({"@id": sec_iri(suffix), "@type": "@id", "@container": "@set"});
+    json!({
+        "@context": {
+            "@protected": true,
+            "id": "@id",
+            "type": "@type",
+            "alsoKnownAs": {
+                "@id": "https://www.w3.org/ns/activitystreams#alsoKnownAs",
+                "@type": "@id"
+            },
+            "assertionMethod": relationship("assertionMethod"),
+            "authentication": relationship("authenticationMethod"),
+            "capabilityDelegation": relationship("capabilityDelegationMethod"),
+            "capabilityInvocation": relationship("capabilityInvocationMethod"),
+            "controller": {"@id": sec_iri("controller"), "@type": "@id"},
+            "keyAgreement": relationship("keyAgreementMethod"),
+            "service": {
+                "@id": "https://www.w3.org/ns/did#service",
+                "@type": "@id",
+                "@context": {
+                    "@protected": true,
+                    "id": "@id",
+                    "type": "@type",
+                    "serviceEndpoint": {
+                        "@id": "https://www.w3.org/ns/did#serviceEndpoint",
+                        "@type": "@id"
+                    }
+                }
+            },
+            "verificationMethod": {"@id": sec_iri("verificationMethod"), "@type": "@id"}
+        }
+    })
+}
+
+/// The context of a verification method suite: a key type whose public key is given by the
+/// `public_key` term, and optionally a proof type.
+fn suite(key_type: &str, public_key: (&str, Json), proof_type: Option<&str>) -> Json {
+    const DATE_TIME: &str = "http://www.w3.org/2001/XMLSchema#dateTime";
+    let (public_key_term, public_key) = public_key;
+    let mut context = json!({
+        "@protected": true,
+        "id": "@id",
+        "type": "@type",
+        "proof": {"@id": sec_iri("proof"), "@type": "@id", "@container": "@graph"},
+    });
+    context[key_type] = json!({
+        "@id": sec_iri(key_type),
+        "@context": {
+            "@protected": true,
+            "id": "@id",
+            "type": "@type",
+            "controller": {"@id": sec_iri("controller"), "@type": "@id"},
+            "revoked": {"@id": sec_iri("revoked"), "@type": DATE_TIME},
+            public_key_term: public_key
+        }
+    });
+    if let Some(proof_type) = proof_type {
+        context[proof_type] = json!({
+            "@id": sec_iri(proof_type),
+            "@context": {
+                "@protected": true,
+                "id": "@id",
+                "type": "@type",
+                "challenge": sec_iri("challenge"),
+                "created": {"@id": sec_iri("created"), "@type": DATE_TIME},
+                "domain": sec_iri("domain"),
+                "expires": {"@id": sec_iri("expiration"), "@type": DATE_TIME},
+                "jws": sec_iri("jws"),
+                "nonce": sec_iri("nonce"),
+                "proofPurpose": {"@id": sec_iri("proofPurpose"), "@type": "@vocab"},
+                "proofValue": {"@id": sec_iri("proofValue"), "@type": sec_iri("multibase")},
+                "verificationMethod": {"@id": sec_iri("verificationMethod"), "@type": "@id"}
+            }
+        });
+    }
+    json!({ "@context": context })
+}
+
+fn sec_iri(suffix: &str) -> String {
+    format!("{}{}", SEC, suffix)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::router::Rewrite;
+    use crate::test_server::{block_on, Response, TestServer};
+    use crate::ttl::from_ttl;
+
+    #[test]
+    fn did_key() {
+        let did = "did:key:z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK";
+        let expected = sorted(from_ttl(&format!(
+            "@prefix sec: <https://w3id.org/security#> .
+            <{did}> sec:verificationMethod <{did}#{key}> .
+            <{did}#{key}> a sec:Ed25519VerificationKey2020 ;
+                sec:controller <{did}> ;
+                sec:publicKeyMultibase \"{key}\"^^sec:multibase .
+            <{did}> sec:authenticationMethod <{did}#{key}> ;
+                sec:assertionMethod <{did}#{key}> ;
+                sec:capabilityDelegationMethod <{did}#{key}> ;
+                sec:capabilityInvocationMethod <{did}#{key}> .",
+            did = did,
+            key = &did["did:key:".len()..],
+        )));
+        let lookup =
+            |iri: &str| block_on(DidLookup::new().lookup(&om::NamedNode::new(iri).unwrap()));
+        assert_eq!(lookup(did).map(sorted), Ok(expected.clone()));
+        assert_eq!(
+            lookup(&format!("{}#{}", did, &did["did:key:".len()..])).map(sorted),
+            Ok(expected)
+        );
+
+        let x25519 = lookup("did:key:z6LSeu9HkTHSfLLeUs2nnzUSNedgDUevfNQgQjQC23ZCit6F").unwrap();
+        assert_eq!(x25519.into_iter().count(), 5);
+        let secp256k1 = "did:key:zQ3shokFTS3brHcDQrn82RUDfCZESWL1ZdCEJwekUDPQiYBme";
+        assert!(matches!(
+            lookup(secp256k1),
+            Err(LookupError::UnsupportedContentType(_))
+        ));
+
+        let short_key = format!(
+            "did:key:{}",
+            multibase::encode(Base::Base58Btc, [&[0xed, 0x01][..], &[7; 31]].concat())
+        );
+        for iri in &[
+            &short_key,
+            "did:key:6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK",
+            "did:key:z0OIl",
+            "did:example:a",
+        ] {
+            assert!(
+                matches!(lookup(iri), Err(LookupError::Refused(_))),
+                "{}",
+                iri
+            );
+        }
+    }
+
+    #[test]
+    fn did_web() {
+        let server = TestServer::start(|req| {
+            let did = match req.path.as_str() {
+                "/.well-known/did.json" => "did:web:example.com",
+                "/user/alice/did.json" => "did:web:example.com:user:alice",
+                _ => return Response::status(404),
+            };
+            let document = json!({
+                "@context": [DID_V1, ED25519_2020_V1],
+                "id": did,
+                "alsoKnownAs": ["https://example.com/alice"],
+                "verificationMethod": [{
+                    "id": format!("{}#key-1", did),
+                    "type": "Ed25519VerificationKey2020",
+                    "controller": did,
+                    "publicKeyMultibase": "z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK"
+                }],
+                "authentication": [format!("{}#key-1", did)]
+            });
+            Response::ok("application/did+ld+json", document.to_string())
+        });
+        let base = server.url("");
+        let web = Rewrite::new(
+            HttpLookup::new().with_context_loader(ContextCache::new().with_did_contexts()),
+            move |iri: &str| Some(iri.replacen("https://example.com", &base, 1)),
+        );
+        let dids = DidLookup::new().with_web_lookup(web);
+        let lookup = |iri: &str| block_on(dids.lookup(&om::NamedNode::new(iri).unwrap()));
+        let expected = |did: &str| {
+            Ok(sorted(from_ttl(&format!(
+                "@prefix sec: <https://w3id.org/security#> .
+                <{did}> <https://www.w3.org/ns/activitystreams#alsoKnownAs>
+                    <https://example.com/alice> ;
+                    sec:verificationMethod <{did}#key-1> ;
+                    sec:authenticationMethod <{did}#key-1> .
+                <{did}#key-1> a sec:Ed25519VerificationKey2020 ;
+                    sec:controller <{did}> ;
+                    sec:publicKeyMultibase
+                        \"z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK\"^^sec:multibase .",
+                did = did
+            ))))
+        };
+
+        assert_eq!(
+            lookup("did:web:example.com").map(sorted),
+            expected("did:web:example.com")
+        );
+        assert_eq!(
+            lookup("did:web:example.com:user:alice").map(sorted),
+            expected("did:web:example.com:user:alice")
+        );
+        assert_eq!(
+            lookup("did:web:example.com:user:bob"),
+            Err(LookupError::NotFound)
+        );
+        assert_eq!(
+            did_web_url("localhost%3A8443:a:b").as_deref(),
+            Some("https://localhost:8443/a/b/did.json")
+        );
+        assert_eq!(
+            did_web_url("localhost%3a8443:%7Ealice:caf%C3%A9").as_deref(),
+            Some("https://localhost:8443/~alice/café/did.json")
+        );
+        assert_eq!(did_web_url("example.com:%7"), None);
+        assert_eq!(did_web_url("example.com:%ZZ"), None);
+    }
+
+    fn sorted(graph: Graph) -> Vec<String> {
+        let mut triples: Vec<String> = graph.into_iter().map(|t| t.to_string()).collect();
+        triples.sort();
+        triples
+    }
+}
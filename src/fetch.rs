@@ -0,0 +1,87 @@
+//! Retrieving documents without parsing them, for lookups that keep the raw bytes around.
+
+use crate::jsonld::DocumentLoader;
+use crate::lookup::{Lookup, LookupError};
+use crate::parse::{document_media_type, parse, JSON_LD};
+use crate::rdf_graph::Graph;
+use async_trait::async_trait;
+use oxigraph::model as om;
+
+/// A document as retrieved, before parsing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawDocument {
+    /// Where the document was found, e.g. after following redirects. Relative iris in the
+    /// document are resolved against it.
+    pub location: String,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+    /// The `ETag` of this version of the document, if it has one.
+    pub etag: Option<String>,
+    /// The `Cache-Control` directives the document came with, if any.
+    pub cache_control: Option<String>,
+}
+
+/// The result of a [`Fetch`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fetched {
+    Document(RawDocument),
+    /// The document still has the etag that was given. Fresh `Cache-Control` directives may come
+    /// with the confirmation.
+    NotModified {
+        cache_control: Option<String>,
+    },
+}
+
+/// Retrieves documents as bytes. Implementations that understand validators should make a
+/// conditional request when given an etag.
+#[async_trait]
+pub trait Fetch {
+    async fn fetch(&self, iri: &om::NamedNode, etag: Option<&str>) -> Result<Fetched, LookupError>;
+}
+
+/// Makes any [`Lookup`] a [`Fetch`] by writing out the graphs it finds as N-Triples, so that
+/// lookups that don't keep the raw document, such as a [`RouterLookup`](crate::RouterLookup), can
+/// be wrapped in a [`CachingLookup`](crate::CachingLookup).
+///
+/// There are no validators or cache directives, so a cache only reuses the graphs for its
+/// default max age, or indefinitely when offline.
+pub struct LookupFetch<L>(pub L);
+
+#[async_trait]
+impl<L: Lookup + Send + Sync> Fetch for LookupFetch<L> {
+    async fn fetch(
+        &self,
+        iri: &om::NamedNode,
+        _etag: Option<&str>,
+    ) -> Result<Fetched, LookupError> {
+        let body: String = self
+            .0
+            .lookup(iri)
+            .await?
+            .into_iter()
+            .map(|triple| format!("{}\n", triple))
+            .collect();
+        Ok(Fetched::Document(RawDocument {
+            location: iri.as_str().to_string(),
+            content_type: Some("application/n-triples".into()),
+            body: body.into_bytes(),
+            etag: None,
+            cache_control: None,
+        }))
+    }
+}
+
+impl RawDocument {
+    /// Parse the document according to its content type, loading any JSON-LD contexts it needs
+    /// with `contexts`.
+    pub async fn parse(
+        &self,
+        contexts: &(dyn DocumentLoader + Send + Sync),
+    ) -> Result<Graph, LookupError> {
+        let location = Some(self.location.as_str());
+        match document_media_type(self.content_type.as_deref(), location)?.as_str() {
+            JSON_LD => Graph::from_json_ld(&self.body, location, contexts).await,
+            media_type => parse(&self.body, media_type, location),
+        }
+    }
+}
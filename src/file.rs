@@ -0,0 +1,241 @@
+//! Looking up documents stored as local files, e.g. fixture supergraphs or mirrors for offline
+//! crawls.
+
+use crate::jsonld::{ContextCache, DocumentLoader};
+use crate::lookup::{Lookup, LookupError};
+use crate::parse::{media_type_for_extension, parse, EXTENSIONS, JSON_LD};
+use crate::rdf_graph::Graph;
+use alloc::collections::BTreeMap;
+use async_trait::async_trait;
+use oxigraph::model as om;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Dereferences iris by reading files from disk. The format of each file is decided by its
+/// extension and relative iris within it are resolved against the iri it was looked up as.
+///
+/// Iris are mapped to files in two ways, tried in order:
+///
+/// - A manifest lists iris along with the file holding each.
+/// - Prefix rules map every iri starting with some prefix to a directory. The rest of the iri is
+///   taken as a path within the directory. When no file exists at that path, a file with the same
+///   name plus one of the known extensions is looked for, so `https://example.com/people/alice`
+///   may be stored as `people/alice.ttl`. The longest matching prefix wins.
+///
+/// Iris that would resolve to a file outside of the directory are refused.
+#[derive(Clone)]
+pub struct FileLookup {
+    files: BTreeMap<String, PathBuf>,
+    prefixes: Vec<(String, PathBuf)>,
+    contexts: Arc<dyn DocumentLoader + Send + Sync>,
+}
+
+impl FileLookup {
+    /// A lookup which knows of no files.
+    pub fn new() -> Self {
+        Self {
+            files: BTreeMap::new(),
+            prefixes: Vec::new(),
+            contexts: Arc::new(ContextCache::new()),
+        }
+    }
+
+    /// Serve `iri` from the file at `path`.
+    pub fn with_file(mut self, iri: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self.files.insert(iri.into(), path.into());
+        self
+    }
+
+    /// Serve iris starting with `prefix` from files in `dir`.
+    pub fn with_prefix(mut self, prefix: impl Into<String>, dir: impl Into<PathBuf>) -> Self {
+        self.prefixes.push((prefix.into(), dir.into()));
+        self.prefixes
+            .sort_by_key(|(prefix, _)| core::cmp::Reverse(prefix.len()));
+        self
+    }
+
+    /// Serve the files listed in a manifest. The manifest is a JSON object mapping iris to paths,
+    /// which are relative to the directory containing the manifest:
+    ///
+    /// ```json
+    /// { "https://example.com/alice": "people/alice.ttl" }
+    /// ```
+    pub fn with_manifest(mut self, manifest: impl AsRef<Path>) -> io::Result<Self> {
+        let manifest = manifest.as_ref();
+        let dir = manifest.parent().unwrap_or_else(|| Path::new(""));
+        let files: BTreeMap<String, PathBuf> = serde_json::from_slice(&std::fs::read(manifest)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        for (iri, path) in files {
+            self.files.insert(iri, dir.join(path));
+        }
+        Ok(self)
+    }
+
+    /// Load the remote contexts of JSON-LD documents with `loader`.
+    pub fn with_context_loader(
+        mut self,
+        loader: impl DocumentLoader + Send + Sync + 'static,
+    ) -> Self {
+        self.contexts = Arc::new(loader);
+        self
+    }
+
+    /// The file holding the document at `iri`.
+    fn path(&self, iri: &str) -> Result<PathBuf, LookupError> {
+        if let Some(path) = self.files.get(iri) {
+            return Ok(path.clone());
+        }
+        let (prefix, dir) = self
+            .prefixes
+            .iter()
+            .find(|(prefix, _)| iri.starts_with(prefix.as_str()))
+            .ok_or_else(|| LookupError::Refused(format!("no file is configured for {}", iri)))?;
+        let rest = &iri[prefix.len()..];
+        let rest = rest.split(['?', '#']).next().unwrap_or_default();
+        let escapes = rest
+            .split('/')
+            .any(|segment| matches!(segment, "" | "." | "..") || segment.contains('\\'));
+        if escapes {
+            return Err(LookupError::Refused(format!(
+                "{} does not name a file within {}",
+                iri,
+                dir.display()
+            )));
+        }
+        let path = dir.join(rest);
+        if path.is_file() {
+            return Ok(path);
+        }
+        EXTENSIONS
+            .iter()
+            .map(|(ext, _)| {
+                let mut with_ext = path.clone().into_os_string();
+                with_ext.push(".");
+                with_ext.push(ext);
+                PathBuf::from(with_ext)
+            })
+            .find(|candidate| candidate.is_file())
+            .ok_or(LookupError::NotFound)
+    }
+}
+
+impl Default for FileLookup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Lookup for FileLookup {
+    async fn lookup(&self, iri: &om::NamedNode) -> Result<Graph, LookupError> {
+        let path = self.path(iri.as_str())?;
+        let media_type = path
+            .to_str()
+            .and_then(media_type_for_extension)
+            .ok_or_else(|| {
+                LookupError::UnsupportedContentType(format!("file {}", path.display()))
+            })?;
+        let body = std::fs::read(&path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => LookupError::NotFound,
+            _ => LookupError::Transport(format!("reading {}: {}", path.display(), e)),
+        })?;
+        let base = Some(iri.as_str());
+        match media_type {
+            JSON_LD => Graph::from_json_ld(&body, base, &*self.contexts).await,
+            media_type => parse(&body, media_type, base),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ttl::from_ttl;
+    use futures::executor::block_on;
+    use std::fs;
+
+    #[test]
+    fn prefixes_and_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("people")).unwrap();
+        fs::create_dir_all(root.join("other")).unwrap();
+        fs::write(
+            root.join("people/alice.ttl"),
+            "<> <http://schema.org/name> \"alice\" .",
+        )
+        .unwrap();
+        fs::write(
+            root.join("people/bob.jsonld"),
+            r#"{"@id": "", "http://schema.org/name": "bob"}"#,
+        )
+        .unwrap();
+        fs::write(
+            root.join("people/carol.nt"),
+            "<http://example.com/people/carol.nt> <http://schema.org/name> \"carol\" .",
+        )
+        .unwrap();
+        fs::write(
+            root.join("other/dave.nt"),
+            "<urn:dave> <http://schema.org/name> \"dave\" .",
+        )
+        .unwrap();
+        fs::write(root.join("other/erin.txt"), "").unwrap();
+        fs::write(root.join("secret.ttl"), "<urn:s> <urn:s> <urn:s> .").unwrap();
+        fs::write(
+            root.join("manifest.json"),
+            r#"{"urn:dave": "other/dave.nt", "urn:erin": "other/erin.txt"}"#,
+        )
+        .unwrap();
+
+        let files = FileLookup::new()
+            .with_prefix("http://example.com/", root.join("other"))
+            .with_prefix("http://example.com/people/", root.join("people"))
+            .with_manifest(root.join("manifest.json"))
+            .unwrap();
+        let lookup = |iri: &str| block_on(files.lookup(&om::NamedNode::new(iri).unwrap()));
+        let named = |iri: &str, name: &str| {
+            Ok(from_ttl(&format!(
+                "<{}> <http://schema.org/name> \"{}\" .",
+                iri, name
+            )))
+        };
+
+        let alice = "http://example.com/people/alice";
+        assert_eq!(lookup(alice), named(alice, "alice"));
+        let bob = "http://example.com/people/bob";
+        assert_eq!(lookup(bob), named(bob, "bob"));
+        let carol = "http://example.com/people/carol.nt";
+        assert_eq!(lookup(carol), named(carol, "carol"));
+        assert_eq!(lookup("urn:dave"), named("urn:dave", "dave"));
+        assert_eq!(lookup("http://example.com/dave"), named("urn:dave", "dave"));
+        assert_eq!(
+            lookup("http://example.com/people/mallory"),
+            Err(LookupError::NotFound)
+        );
+        assert!(matches!(
+            lookup("urn:erin"),
+            Err(LookupError::UnsupportedContentType(_))
+        ));
+        for iri in &[
+            "http://example.com/people/../secret",
+            "http://example.com/people/%2e%2e/secret",
+            "http://example.com/people/..%5Csecret",
+            "http://example.org/alice",
+        ] {
+            assert!(
+                matches!(
+                    lookup(iri),
+                    Err(LookupError::Refused(_)) | Err(LookupError::NotFound)
+                ),
+                "{}",
+                iri
+            );
+        }
+        assert!(matches!(
+            lookup("http://example.com/people/../secret"),
+            Err(LookupError::Refused(_))
+        ));
+    }
+}
@@ -0,0 +1,150 @@
+//! A priority queue of documents waiting to be looked up.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::cmp::{Ordering, Reverse};
+use serde::{Deserialize, Serialize};
+
+/// Documents ordered by descending priority, then by iri. Each document appears at most once;
+/// enqueueing a document twice keeps the higher of the two priorities.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "BTreeMap<String, f64>", into = "BTreeMap<String, f64>")]
+pub struct Frontier {
+    priorities: BTreeMap<String, Priority>,
+    queue: BTreeSet<(Reverse<Priority>, String)>,
+}
+
+impl Frontier {
+    pub fn push(&mut self, iri: String, priority: f64) {
+        let priority = Priority::new(priority);
+        match self.priorities.get(&iri) {
+            Some(old) if *old >= priority => return,
+            Some(old) => {
+                self.queue.remove(&(Reverse(*old), iri.clone()));
+            }
+            None => {}
+        }
+        self.priorities.insert(iri.clone(), priority);
+        self.queue.insert((Reverse(priority), iri));
+    }
+
+    /// Remove and return the most interesting document.
+    pub fn pop(&mut self) -> Option<(String, f64)> {
+        let first = self.queue.iter().next()?.clone();
+        self.queue.remove(&first);
+        let (Reverse(priority), iri) = first;
+        self.priorities.remove(&iri);
+        Some((iri, priority.0))
+    }
+
+    pub fn remove(&mut self, iri: &str) {
+        if let Some(priority) = self.priorities.remove(iri) {
+            self.queue.remove(&(Reverse(priority), iri.to_string()));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.priorities.is_empty()
+    }
+
+    /// Documents in the order they will be popped.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.queue
+            .iter()
+            .map(|(Reverse(priority), iri)| (iri.as_str(), priority.0))
+    }
+}
+
+impl From<BTreeMap<String, f64>> for Frontier {
+    fn from(other: BTreeMap<String, f64>) -> Self {
+        let mut ret = Frontier::default();
+        for (iri, priority) in other {
+            ret.push(iri, priority);
+        }
+        ret
+    }
+}
+
+impl From<Frontier> for BTreeMap<String, f64> {
+    fn from(other: Frontier) -> Self {
+        other
+            .priorities
+            .into_iter()
+            .map(|(iri, priority)| (iri, priority.0))
+            .collect()
+    }
+}
+
+/// A totally ordered, finite f64. NaN is treated as zero and infinities as the largest finite
+/// values, which unlike them survive a round trip through JSON.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Priority(f64);
+
+impl Priority {
+    fn new(p: f64) -> Self {
+        Priority(if p.is_nan() {
+            0.0
+        } else {
+            p.clamp(f64::MIN, f64::MAX)
+        })
+    }
+}
+
+impl Eq for Priority {}
+
+impl PartialOrd for Priority {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Priority {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.partial_cmp(&other.0).unwrap_or(Ordering::Equal)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_highest_priority() {
+        let mut frontier = Frontier::default();
+        frontier.push("did:a".into(), 1.0);
+        frontier.push("did:b".into(), 2.0);
+        frontier.push("did:c".into(), 1.0);
+        frontier.push("did:a".into(), 3.0);
+        frontier.push("did:b".into(), 0.0);
+        frontier.push("did:d".into(), f64::NAN);
+        assert_eq!(
+            frontier.iter().collect::<Vec<_>>(),
+            [
+                ("did:a", 3.0),
+                ("did:b", 2.0),
+                ("did:c", 1.0),
+                ("did:d", 0.0)
+            ]
+        );
+        frontier.remove("did:b");
+        assert_eq!(frontier.pop(), Some(("did:a".into(), 3.0)));
+        assert_eq!(frontier.pop(), Some(("did:c".into(), 1.0)));
+        assert_eq!(frontier.pop(), Some(("did:d".into(), 0.0)));
+        assert_eq!(frontier.pop(), None);
+        assert!(frontier.is_empty());
+    }
+
+    #[test]
+    fn round_trips_infinite_priorities() {
+        let mut frontier = Frontier::default();
+        frontier.push("did:a".into(), f64::INFINITY);
+        frontier.push("did:b".into(), f64::NEG_INFINITY);
+        frontier.push("did:c".into(), 1.0);
+        let json = serde_json::to_string(&frontier).unwrap();
+        let resumed: Frontier = serde_json::from_str(&json).unwrap();
+        assert_eq!(resumed, frontier);
+        assert_eq!(
+            resumed.iter().collect::<Vec<_>>(),
+            [("did:a", f64::MAX), ("did:c", 1.0), ("did:b", f64::MIN)]
+        );
+    }
+}
@@ -0,0 +1,329 @@
+//! Looking up documents over http(s).
+
+use crate::fetch::{Fetch, Fetched, RawDocument};
+use crate::jsonld::{json_error, ContextCache, DocumentLoader};
+use crate::lookup::{Lookup, LookupError};
+use crate::parse::accept;
+use crate::rdf_graph::Graph;
+use async_trait::async_trait;
+use oxigraph::model as om;
+use reqwest::header::{
+    HeaderMap, HeaderName, ACCEPT, CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+};
+use reqwest::redirect::Policy;
+use reqwest::{Client, StatusCode, Url};
+use serde_json::Value as Json;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+const DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Dereferences http and https iris, asking for any rdf format we can parse.
+///
+/// JSON-LD contexts are loaded from an empty [`ContextCache`] unless told otherwise, so by default
+/// only JSON-LD documents with embedded contexts can be read.
+///
+/// Like reqwest, which it is built on, `HttpLookup` must be run within a tokio 1 runtime.
+#[derive(Clone)]
+pub struct HttpLookup {
+    client: Client,
+    timeout: Duration,
+    max_redirects: usize,
+    max_size: u64,
+    contexts: Arc<dyn DocumentLoader + Send + Sync>,
+}
+
+impl HttpLookup {
+    pub fn new() -> Self {
+        Self {
+            client: Client::new(),
+            timeout: DEFAULT_TIMEOUT,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            max_size: DEFAULT_MAX_SIZE,
+            contexts: Arc::new(ContextCache::new()),
+        }
+        .rebuild()
+    }
+
+    /// Give up on a document if it has not been fully retrieved within `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.rebuild()
+    }
+
+    /// Follow at most `max_redirects` redirects per document.
+    pub fn with_max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self.rebuild()
+    }
+
+    /// Refuse documents larger than `max_size` bytes.
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Load the remote contexts of JSON-LD documents with `loader`. Another `HttpLookup` may be
+    /// used to fetch contexts from the web.
+    pub fn with_context_loader(
+        mut self,
+        loader: impl DocumentLoader + Send + Sync + 'static,
+    ) -> Self {
+        self.contexts = Arc::new(loader);
+        self
+    }
+
+    fn rebuild(mut self) -> Self {
+        self.client = Client::builder()
+            .timeout(self.timeout)
+            .redirect(Policy::limited(self.max_redirects))
+            .build()
+            .expect("tls backend failed to initialize");
+        self
+    }
+}
+
+impl Default for HttpLookup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpLookup {
+    async fn get(
+        &self,
+        iri: &str,
+        accept: &str,
+        etag: Option<&str>,
+    ) -> Result<Fetched, LookupError> {
+        let url: Url = iri
+            .parse()
+            .map_err(|_| LookupError::Refused("not a url".into()))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(LookupError::Refused(format!(
+                "{} is not an http(s) url",
+                url
+            )));
+        }
+
+        let mut req = self.client.get(url).header(ACCEPT, accept);
+        if let Some(etag) = etag {
+            req = req.header(IF_NONE_MATCH, etag);
+        }
+        let mut resp = req.send().await.map_err(transport)?;
+        let cache_control = header(resp.headers(), CACHE_CONTROL)?;
+        match resp.status() {
+            StatusCode::NOT_MODIFIED if etag.is_some() => {
+                return Ok(Fetched::NotModified { cache_control })
+            }
+            StatusCode::NOT_FOUND | StatusCode::GONE => return Err(LookupError::NotFound),
+            status if !status.is_success() => {
+                return Err(LookupError::Transport(format!(
+                    "unsucsessful GET: {}",
+                    status
+                )))
+            }
+            _ => {}
+        }
+        let content_type = header(resp.headers(), CONTENT_TYPE)
+            .map_err(|_| LookupError::UnsupportedContentType("invalid content-type".into()))?;
+        let etag = header(resp.headers(), ETAG)?;
+        if matches!(resp.content_length(), Some(len) if len > self.max_size) {
+            return Err(LookupError::TooLarge {
+                limit: self.max_size,
+            });
+        }
+        let location = resp.url().to_string();
+        let mut body = Vec::new();
+        while let Some(chunk) = resp.chunk().await.map_err(transport)? {
+            body.extend_from_slice(&chunk);
+            if body.len() as u64 > self.max_size {
+                return Err(LookupError::TooLarge {
+                    limit: self.max_size,
+                });
+            }
+        }
+        Ok(Fetched::Document(RawDocument {
+            location,
+            content_type,
+            body,
+            etag,
+            cache_control,
+        }))
+    }
+
+    /// Retrieve a document unconditionally.
+    async fn get_document(&self, iri: &str, accept: &str) -> Result<RawDocument, LookupError> {
+        match self.get(iri, accept, None).await? {
+            Fetched::Document(doc) => Ok(doc),
+            Fetched::NotModified { .. } => unreachable!("no etag was given"),
+        }
+    }
+}
+
+#[async_trait]
+impl Lookup for HttpLookup {
+    async fn lookup(&self, iri: &om::NamedNode) -> Result<Graph, LookupError> {
+        let doc = self.get_document(iri.as_str(), &accept()).await?;
+        doc.parse(&*self.contexts).await
+    }
+}
+
+#[async_trait]
+impl Fetch for HttpLookup {
+    async fn fetch(&self, iri: &om::NamedNode, etag: Option<&str>) -> Result<Fetched, LookupError> {
+        self.get(iri.as_str(), &accept(), etag).await
+    }
+}
+
+/// Retrieves remote JSON-LD contexts.
+#[async_trait]
+impl DocumentLoader for HttpLookup {
+    async fn load(&self, url: &str) -> Result<Json, LookupError> {
+        let doc = self
+            .get_document(url, "application/ld+json, application/json")
+            .await?;
+        serde_json::from_slice(&doc.body).map_err(json_error)
+    }
+}
+
+fn header(headers: &HeaderMap, name: HeaderName) -> Result<Option<String>, LookupError> {
+    headers
+        .get(&name)
+        .map(|value| value.to_str().map(str::to_string))
+        .transpose()
+        .map_err(|_| LookupError::Transport(format!("invalid {} header", name)))
+}
+
+/// The lookup error for a failed request or an interrupted response.
+pub(crate) fn transport(err: reqwest::Error) -> LookupError {
+    if err.is_timeout() {
+        LookupError::Timeout
+    } else {
+        LookupError::Transport(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_server::{block_on, Response, TestServer};
+    use crate::ttl::from_ttl;
+
+    const TTL: &str = "<a> <http://schema.org/name> \"a\" .";
+
+    #[test]
+    fn negotiates_and_parses() {
+        let server = TestServer::start(|req| match req.path.as_str() {
+            "/ttl" => Response::ok("Text/Turtle; charset=\"UTF-8\"", TTL),
+            "/nt" => Response::ok(
+                "application/n-triples",
+                "<http://example.com/a> <http://schema.org/name> \"a\" .",
+            ),
+            "/moved" => Response::redirect("/doc/ttl"),
+            "/doc/ttl" => Response::ok("text/turtle", TTL),
+            "/doc.ttl" => Response::ok("application/octet-stream", TTL),
+            "/html" => Response::ok("text/html", "<html></html>"),
+            "/latin1" => Response::ok("text/turtle; charset=iso-8859-1", TTL),
+            _ => Response::status(404),
+        });
+        let http = HttpLookup::new();
+        let lookup = |path: &str| block_on(http.lookup(&server.iri(path)));
+
+        let expected = |base: &str| {
+            from_ttl(&format!(
+                "<{}> <http://schema.org/name> \"a\" .",
+                server.iri(base).as_str()
+            ))
+        };
+        assert_eq!(lookup("/ttl"), Ok(expected("/a")));
+        assert_eq!(
+            lookup("/nt"),
+            Ok(from_ttl(
+                "<http://example.com/a> <http://schema.org/name> \"a\" ."
+            ))
+        );
+        assert_eq!(lookup("/moved"), Ok(expected("/doc/a")));
+        assert_eq!(lookup("/doc.ttl"), Ok(expected("/a")));
+        assert_eq!(lookup("/missing"), Err(LookupError::NotFound));
+        assert_eq!(
+            lookup("/html"),
+            Err(LookupError::UnsupportedContentType("text/html".into()))
+        );
+        assert_eq!(
+            lookup("/latin1"),
+            Err(LookupError::UnsupportedContentType(
+                "text/turtle; charset=iso-8859-1".into()
+            ))
+        );
+
+        let accepts = server
+            .requests()
+            .into_iter()
+            .map(|req| req.headers["accept"].clone())
+            .collect::<Vec<_>>();
+        assert!(accepts.iter().all(|a| a
+            == "text/turtle, application/n-triples;q=0.9, application/rdf+xml;q=0.8, \
+                application/trig;q=0.7, application/n-quads;q=0.7, application/ld+json;q=0.6"));
+    }
+
+    #[test]
+    fn json_ld() {
+        let server = TestServer::start(|req| match req.path.as_str() {
+            "/doc" => Response::ok(
+                "application/ld+json",
+                r#"{"@context": "/context", "@id": "a", "name": "a"}"#,
+            ),
+            "/context" => Response::ok(
+                "application/ld+json",
+                r#"{"@context": {"name": "http://schema.org/name"}}"#,
+            ),
+            _ => Response::status(404),
+        });
+        let expected = from_ttl(&format!(
+            "<{}> <http://schema.org/name> \"a\" .",
+            server.iri("/a").as_str()
+        ));
+
+        let http = HttpLookup::new().with_context_loader(HttpLookup::new());
+        assert_eq!(block_on(http.lookup(&server.iri("/doc"))), Ok(expected));
+        let requests = server.requests();
+        assert_eq!(requests[1].path, "/context");
+        assert_eq!(
+            requests[1].headers["accept"],
+            "application/ld+json, application/json"
+        );
+
+        assert!(matches!(
+            block_on(HttpLookup::new().lookup(&server.iri("/doc"))),
+            Err(LookupError::Refused(_))
+        ));
+    }
+
+    #[test]
+    fn limits() {
+        let server = TestServer::start(|req| match req.path.as_str() {
+            "/big" => Response::ok("text/turtle", TTL.repeat(100)),
+            "/loop" => Response::redirect("/loop"),
+            _ => Response::status(500),
+        });
+        let http = HttpLookup::new().with_max_size(100).with_max_redirects(3);
+        let lookup = |path: &str| block_on(http.lookup(&server.iri(path)));
+
+        assert_eq!(lookup("/big"), Err(LookupError::TooLarge { limit: 100 }));
+        assert!(matches!(lookup("/loop"), Err(LookupError::Transport(_))));
+        assert!(matches!(lookup("/error"), Err(LookupError::Transport(_))));
+        let loops = server
+            .requests()
+            .into_iter()
+            .filter(|req| req.path == "/loop")
+            .count();
+        assert!(loops <= 1 + 3);
+        assert!(matches!(
+            block_on(http.lookup(&om::NamedNode::new("did:example:a").unwrap())),
+            Err(LookupError::Refused(_))
+        ));
+    }
+}
@@ -0,0 +1,487 @@
+//! Looking up documents stored on ipfs.
+//!
+//! Blocks are retrieved one at a time from the HTTP API of an ipfs node and each is checked
+//! against the hash in its cid, so a misbehaving node can't substitute a different document.
+//! Files split over several blocks are reassembled by following their UnixFS links.
+
+use crate::http::transport;
+use crate::lookup::{Lookup, LookupError};
+use crate::parse::parse;
+use crate::rdf_graph::Graph;
+use async_trait::async_trait;
+use cid::{Cid, Codec};
+use core::convert::TryFrom;
+use oxigraph::model as om;
+use reqwest::{Client, Url};
+use std::time::Duration;
+
+const DEFAULT_API: &str = "http://127.0.0.1:5001";
+const DEFAULT_MEDIA_TYPE: &str = "text/turtle";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024;
+
+const DEFAULT_MAX_BLOCKS: usize = 16 * 1024;
+
+/// Dereferences `ipfs://<cid>`, `ipfs://ipfs/<cid>` and `/ipfs/<cid>` iris. The last form may
+/// also appear as the path of a gateway url, e.g. `https://ipfs.io/ipfs/<cid>`, in which case
+/// the document is still retrieved from the configured node rather than from the gateway.
+///
+/// Documents on ipfs carry no media type so they are all assumed to be of the same type, Turtle
+/// unless told otherwise.
+///
+/// Like reqwest, which it is built on, `IpfsLookup` must be run within a tokio 1 runtime.
+#[derive(Clone, Debug)]
+pub struct IpfsLookup {
+    client: Client,
+    api: String,
+    media_type: String,
+    max_size: u64,
+    max_blocks: usize,
+}
+
+impl IpfsLookup {
+    /// Use the ipfs node running on this machine.
+    pub fn new() -> Self {
+        Self {
+            client: Client::builder()
+                .timeout(DEFAULT_TIMEOUT)
+                .build()
+                .expect("tls backend failed to initialize"),
+            api: DEFAULT_API.into(),
+            media_type: DEFAULT_MEDIA_TYPE.into(),
+            max_size: DEFAULT_MAX_SIZE,
+            max_blocks: DEFAULT_MAX_BLOCKS,
+        }
+    }
+
+    /// Use the HTTP API of the ipfs node at `api`, e.g. `http://127.0.0.1:5001`.
+    pub fn with_api(mut self, api: impl Into<String>) -> Self {
+        self.api = api.into();
+        self
+    }
+
+    /// Parse documents as `media_type`.
+    pub fn with_media_type(mut self, media_type: impl Into<String>) -> Self {
+        self.media_type = media_type.into();
+        self
+    }
+
+    /// Refuse documents larger than `max_size` bytes. The dag-pb nodes linking a file's blocks
+    /// together count towards its size.
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Refuse documents made of more than `max_blocks` blocks. Empty blocks can be linked any
+    /// number of times, so the size limit alone does not bound the number of requests.
+    pub fn with_max_blocks(mut self, max_blocks: usize) -> Self {
+        self.max_blocks = max_blocks;
+        self
+    }
+
+    /// Retrieve a single block of at most `limit` bytes, checking that it hashes to `cid`.
+    async fn block(&self, cid: &Cid, limit: u64) -> Result<Vec<u8>, LookupError> {
+        let mut url: Url = format!("{}/api/v0/block/get", self.api.trim_end_matches('/'))
+            .parse()
+            .map_err(|e| LookupError::Refused(format!("invalid ipfs api url: {}", e)))?;
+        url.query_pairs_mut().append_pair("arg", &cid.to_string());
+        let mut resp = self.client.post(url).send().await.map_err(transport)?;
+        if !resp.status().is_success() {
+            return Err(LookupError::Transport(format!(
+                "ipfs node returned {} for {}",
+                resp.status(),
+                cid
+            )));
+        }
+        let too_large = LookupError::TooLarge {
+            limit: self.max_size,
+        };
+        if matches!(resp.content_length(), Some(len) if len > limit) {
+            return Err(too_large);
+        }
+        let mut block = Vec::new();
+        while let Some(chunk) = resp.chunk().await.map_err(transport)? {
+            block.extend_from_slice(&chunk);
+            if block.len() as u64 > limit {
+                return Err(too_large);
+            }
+        }
+        let hash = cid.hash();
+        if hash.algorithm().digest(&block).as_bytes() != hash.as_bytes() {
+            return Err(LookupError::Corrupt(format!(
+                "block does not hash to {}",
+                cid
+            )));
+        }
+        Ok(block)
+    }
+
+    /// Retrieve the contents of the file at `cid`.
+    async fn cat(&self, cid: Cid) -> Result<Vec<u8>, LookupError> {
+        let mut contents = Vec::new();
+        // Bytes retrieved so far, including those of dag-pb nodes.
+        let mut fetched = 0;
+        let mut blocks = 0;
+        // Blocks still to be read, last first.
+        let mut pending = vec![cid];
+        while let Some(cid) = pending.pop() {
+            blocks += 1;
+            if blocks > self.max_blocks {
+                return Err(LookupError::Refused(format!(
+                    "ipfs file has more than {} blocks",
+                    self.max_blocks
+                )));
+            }
+            let block = self.block(&cid, self.max_size - fetched).await?;
+            fetched += block.len() as u64;
+            match cid.codec() {
+                Codec::Raw => contents.extend_from_slice(&block),
+                Codec::DagProtobuf => {
+                    let node = unixfs::File::decode(&block)?;
+                    contents.extend_from_slice(node.data);
+                    pending.extend(node.links.into_iter().rev());
+                }
+                codec => {
+                    return Err(LookupError::UnsupportedContentType(format!(
+                        "ipfs codec {:?}",
+                        codec
+                    )))
+                }
+            }
+        }
+        Ok(contents)
+    }
+}
+
+impl Default for IpfsLookup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Lookup for IpfsLookup {
+    async fn lookup(&self, iri: &om::NamedNode) -> Result<Graph, LookupError> {
+        let cid = cid_of(iri.as_str())?;
+        // A bare `/ipfs/<cid>` path can't serve as a base iri.
+        let base = if iri.as_str().starts_with('/') {
+            format!("ipfs://{}", cid)
+        } else {
+            iri.as_str().to_string()
+        };
+        let contents = self.cat(cid).await?;
+        parse(&contents, &self.media_type, Some(&base))
+    }
+}
+
+/// The cid addressed by an ipfs iri.
+fn cid_of(iri: &str) -> Result<Cid, LookupError> {
+    let cid = if let Some(cid) = iri.strip_prefix("ipfs://ipfs/") {
+        cid
+    } else if let Some(cid) = iri.strip_prefix("ipfs://") {
+        cid
+    } else if let Some(start) = iri.find("/ipfs/").filter(|start| {
+        *start == 0 || iri[..*start].starts_with("http://") || iri[..*start].starts_with("https://")
+    }) {
+        &iri[start + "/ipfs/".len()..]
+    } else {
+        return Err(LookupError::Refused(format!("{} is not an ipfs iri", iri)));
+    };
+    let cid = cid.trim_end_matches('/');
+    if cid.contains(['/', '?', '#']) {
+        return Err(LookupError::Refused(format!(
+            "paths within ipfs directories are not supported: {}",
+            iri
+        )));
+    }
+    Cid::try_from(cid).map_err(|e| LookupError::Refused(format!("invalid cid {}: {}", cid, e)))
+}
+
+/// Just enough of the dag-pb and UnixFS protobuf formats to read files.
+mod unixfs {
+    use super::*;
+
+    const FILE: u64 = 2;
+    const RAW: u64 = 0;
+
+    pub struct File<'a> {
+        pub data: &'a [u8],
+        pub links: Vec<Cid>,
+    }
+
+    impl<'a> File<'a> {
+        /// Decode a dag-pb node holding a UnixFS file or raw data.
+        pub fn decode(block: &'a [u8]) -> Result<Self, LookupError> {
+            let mut unixfs = None;
+            let mut links = Vec::new();
+            for field in fields(block)? {
+                match field {
+                    (1, Field::Bytes(data)) => unixfs = Some(data),
+                    (2, Field::Bytes(link)) => {
+                        for field in fields(link)? {
+                            if let (1, Field::Bytes(hash)) = field {
+                                links.push(
+                                    Cid::try_from(hash).map_err(|e| malformed(e.to_string()))?,
+                                );
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            let mut ty = None;
+            let mut data: &[u8] = &[];
+            for field in fields(unixfs.ok_or_else(|| malformed("node has no data"))?)? {
+                match field {
+                    (1, Field::Varint(t)) => ty = Some(t),
+                    (2, Field::Bytes(d)) => data = d,
+                    _ => {}
+                }
+            }
+            match ty {
+                Some(FILE) | Some(RAW) => Ok(File { data, links }),
+                Some(ty) => Err(LookupError::Refused(format!(
+                    "UnixFS node of type {} is not a file",
+                    ty
+                ))),
+                None => Err(malformed("UnixFS node has no type")),
+            }
+        }
+    }
+
+    pub enum Field<'a> {
+        Varint(u64),
+        Bytes(&'a [u8]),
+        Fixed,
+    }
+
+    /// The fields of a protobuf message, as (field number, value).
+    pub fn fields(mut msg: &[u8]) -> Result<Vec<(u64, Field<'_>)>, LookupError> {
+        let mut ret = Vec::new();
+        while !msg.is_empty() {
+            let key = varint(&mut msg)?;
+            let value = match key & 7 {
+                0 => Field::Varint(varint(&mut msg)?),
+                1 => take(&mut msg, 8).map(|_| Field::Fixed)?,
+                2 => {
+                    let len = varint(&mut msg)? as usize;
+                    Field::Bytes(take(&mut msg, len)?)
+                }
+                5 => take(&mut msg, 4).map(|_| Field::Fixed)?,
+                wire_type => return Err(malformed(format!("unknown wire type {}", wire_type))),
+            };
+            ret.push((key >> 3, value));
+        }
+        Ok(ret)
+    }
+
+    fn take<'a>(msg: &mut &'a [u8], len: usize) -> Result<&'a [u8], LookupError> {
+        if len > msg.len() {
+            return Err(malformed("truncated field"));
+        }
+        let (taken, rest) = msg.split_at(len);
+        *msg = rest;
+        Ok(taken)
+    }
+
+    fn varint(msg: &mut &[u8]) -> Result<u64, LookupError> {
+        let mut ret = 0;
+        for shift in (0..64).step_by(7) {
+            let (byte, rest) = msg
+                .split_first()
+                .ok_or_else(|| malformed("truncated varint"))?;
+            *msg = rest;
+            ret |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(ret);
+            }
+        }
+        Err(malformed("varint too long"))
+    }
+
+    fn malformed(message: impl Into<String>) -> LookupError {
+        LookupError::Parse {
+            message: format!("malformed dag-pb node: {}", message.into()),
+            line: None,
+            column: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_server::{block_on, Response, TestServer};
+    use crate::ttl::from_ttl;
+    use alloc::collections::BTreeMap;
+    use multihash::Code;
+
+    const TTL: &str = "<urn:a> <http://schema.org/name> \"a\" .";
+
+    #[test]
+    fn raw_and_chunked_files() {
+        let mut blocks = Blocks::default();
+        let raw = blocks.raw(TTL.as_bytes());
+        let (head, tail) = TTL.as_bytes().split_at(10);
+        let leaves = vec![blocks.raw(head), blocks.raw(tail)];
+        let file = blocks.dag_pb(2, b"", &leaves);
+        let (server, ipfs) = serve(blocks);
+
+        let lookup = |iri: String| block_on(ipfs.lookup(&om::NamedNode::new_unchecked(iri)));
+        let expected = Ok(from_ttl(TTL));
+        assert_eq!(lookup(format!("ipfs://{}", raw)), expected);
+        assert_eq!(lookup(format!("ipfs://ipfs/{}", file)), expected);
+        assert_eq!(lookup(format!("/ipfs/{}", file)), expected);
+        assert_eq!(lookup(format!("https://ipfs.io/ipfs/{}/", raw)), expected);
+        assert!(file.to_string().starts_with("Qm"));
+
+        let requested = server
+            .requests()
+            .into_iter()
+            .filter(|req| req.path.starts_with("/api/v0/block/get?arg="))
+            .count();
+        assert_eq!(requested, 1 + 3 + 3 + 1);
+    }
+
+    #[test]
+    fn verifies_blocks() {
+        let mut blocks = Blocks::default();
+        let good = blocks.raw(TTL.as_bytes());
+        let bad = blocks.raw(b"<urn:a> <urn:b> <urn:c> .");
+        blocks
+            .0
+            .insert(bad.to_string(), b"<urn:a> <urn:b> <urn:d> .".to_vec());
+        let chunked = blocks.dag_pb(2, b"", &[good.clone(), bad.clone()]);
+        let dir = blocks.dag_pb(1, b"", &[good]);
+        let (_server, ipfs) = serve(blocks);
+        let lookup = |iri: String| block_on(ipfs.lookup(&om::NamedNode::new_unchecked(iri)));
+
+        assert!(matches!(
+            lookup(format!("ipfs://{}", bad)),
+            Err(LookupError::Corrupt(_))
+        ));
+        assert!(matches!(
+            lookup(format!("ipfs://{}", chunked)),
+            Err(LookupError::Corrupt(_))
+        ));
+        assert!(matches!(
+            lookup(format!("ipfs://{}", dir)),
+            Err(LookupError::Refused(_))
+        ));
+        assert!(matches!(
+            lookup(format!("ipfs://{}/doc.ttl", dir)),
+            Err(LookupError::Refused(_))
+        ));
+        for iri in &[
+            "http://example.com/doc",
+            "ipfs://not-a-cid",
+            "did:example:a",
+        ] {
+            assert!(matches!(
+                lookup(iri.to_string()),
+                Err(LookupError::Refused(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn limits_size() {
+        let mut blocks = Blocks::default();
+        let leaves = vec![blocks.raw(TTL.as_bytes()), blocks.raw(TTL.as_bytes())];
+        let file = blocks.dag_pb(2, b"", &leaves);
+        let (_server, ipfs) = serve(blocks);
+        let ipfs = ipfs.with_max_size(TTL.len() as u64 + 1);
+        assert_eq!(
+            block_on(ipfs.lookup(&om::NamedNode::new_unchecked(format!("ipfs://{}", file)))),
+            Err(LookupError::TooLarge {
+                limit: TTL.len() as u64 + 1
+            })
+        );
+
+        let mut blocks = Blocks::default();
+        let raw = blocks.raw(TTL.as_bytes());
+        let (_server, ipfs) = serve(blocks);
+        let ipfs = ipfs.with_max_size(TTL.len() as u64 - 1);
+        assert_eq!(
+            block_on(ipfs.lookup(&om::NamedNode::new_unchecked(format!("ipfs://{}", raw)))),
+            Err(LookupError::TooLarge {
+                limit: TTL.len() as u64 - 1
+            })
+        );
+    }
+
+    #[test]
+    fn limits_blocks() {
+        let mut blocks = Blocks::default();
+        let empty = blocks.raw(b"");
+        let node = blocks.dag_pb(2, b"", &vec![empty; 16]);
+        let file = blocks.dag_pb(2, b"", &vec![node; 16]);
+        let (server, ipfs) = serve(blocks);
+        let ipfs = ipfs.with_max_blocks(100);
+        assert_eq!(
+            block_on(ipfs.lookup(&om::NamedNode::new_unchecked(format!("ipfs://{}", file)))),
+            Err(LookupError::Refused(
+                "ipfs file has more than 100 blocks".into()
+            ))
+        );
+        assert_eq!(server.requests().len(), 100);
+    }
+
+    /// Blocks by cid.
+    #[derive(Default)]
+    struct Blocks(BTreeMap<String, Vec<u8>>);
+
+    impl Blocks {
+        fn raw(&mut self, data: &[u8]) -> Cid {
+            let cid = Cid::new_v1(Codec::Raw, Code::Sha2_256.digest(data));
+            self.0.insert(cid.to_string(), data.to_vec());
+            cid
+        }
+
+        /// Store a UnixFS node of type `ty` as a CIDv0 dag-pb block.
+        fn dag_pb(&mut self, ty: u64, data: &[u8], links: &[Cid]) -> Cid {
+            let mut node = Vec::new();
+            for link in links {
+                let mut pb_link = Vec::new();
+                bytes_field(&mut pb_link, 1, &link.to_bytes());
+                bytes_field(&mut node, 2, &pb_link);
+            }
+            let mut unixfs = Vec::new();
+            varint(&mut unixfs, 1 << 3);
+            varint(&mut unixfs, ty);
+            bytes_field(&mut unixfs, 2, data);
+            bytes_field(&mut node, 1, &unixfs);
+            let cid = Cid::new_v0(Code::Sha2_256.digest(&node)).unwrap();
+            self.0.insert(cid.to_string(), node);
+            cid
+        }
+    }
+
+    fn bytes_field(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+        varint(buf, field << 3 | 2);
+        varint(buf, bytes.len() as u64);
+        buf.extend_from_slice(bytes);
+    }
+
+    fn varint(buf: &mut Vec<u8>, mut n: u64) {
+        while n >= 0x80 {
+            buf.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        buf.push(n as u8);
+    }
+
+    /// Stand in for the block api of an ipfs node.
+    fn serve(blocks: Blocks) -> (TestServer, IpfsLookup) {
+        let server = TestServer::start(move |req| {
+            req.path
+                .strip_prefix("/api/v0/block/get?arg=")
+                .and_then(|cid| blocks.0.get(cid))
+                .map(|block| Response::ok("text/plain", block.clone()))
+                .unwrap_or_else(|| Response::status(500))
+        });
+        let ipfs = IpfsLookup::new().with_api(server.url(""));
+        (server, ipfs)
+    }
+}
@@ -0,0 +1,1342 @@
+//! Reading JSON-LD documents as rdf.
+//!
+//! This implements the parts of JSON-LD 1.1 expansion and deserialization to rdf that verifiable
+//! credentials and DID documents rely on: remote, embedded, property-scoped and type-scoped
+//! contexts, keyword aliases, compact iris, `@vocab`, type coercion, default languages, language,
+//! index, id and type maps, lists, `@nest` and reverse properties. As with other quad formats,
+//! named graphs given with `@graph` are flattened into the document's graph. Values of graph
+//! containers, e.g. a credential's `proof`, are left out instead, so that a signature can't be
+//! mistaken for part of what it signs.
+//!
+//! Remote contexts are retrieved through a [`DocumentLoader`] before any expansion happens.
+//! [`ContextCache`] serves them from memory so documents can be read offline and
+//! deterministically.
+
+use crate::lookup::LookupError;
+use crate::rdf_graph::Graph;
+use alloc::collections::BTreeMap;
+use async_trait::async_trait;
+use oxigraph::model as om;
+use serde_json::{Map, Value as Json};
+use std::sync::Mutex;
+use tap::prelude::*;
+
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+
+/// Give up on documents that reference more remote contexts than this. Protects against
+/// loaders that invent a new context for every url.
+const MAX_REMOTE_CONTEXTS: usize = 64;
+
+/// Give up on contexts nested more deeply than this. Protects against contexts that include
+/// themselves.
+const MAX_CONTEXT_DEPTH: usize = 32;
+
+/// Retrieves the JSON documents JSON-LD refers to, i.e. remote `@context`s.
+#[async_trait]
+pub trait DocumentLoader {
+    async fn load(&self, url: &str) -> Result<Json, LookupError>;
+}
+
+/// Contexts kept in memory. Contexts that are not in the cache are retrieved with the fallback
+/// loader, if there is one, and remembered.
+#[derive(Default)]
+pub struct ContextCache {
+    contexts: Mutex<BTreeMap<String, Json>>,
+    fallback: Option<Box<dyn DocumentLoader + Send + Sync>>,
+}
+
+impl ContextCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `context` as the document at `url`.
+    pub fn with_context(self, url: impl Into<String>, context: Json) -> Self {
+        self.contexts.lock().unwrap().insert(url.into(), context);
+        self
+    }
+
+    /// Retrieve contexts missing from the cache with `loader`.
+    pub fn with_fallback(mut self, loader: impl DocumentLoader + Send + Sync + 'static) -> Self {
+        self.fallback = Some(Box::new(loader));
+        self
+    }
+}
+
+#[async_trait]
+impl DocumentLoader for ContextCache {
+    async fn load(&self, url: &str) -> Result<Json, LookupError> {
+        if let Some(context) = self.contexts.lock().unwrap().get(url) {
+            return Ok(context.clone());
+        }
+        let fallback = self.fallback.as_ref().ok_or_else(|| {
+            LookupError::Refused(format!("context {} is not in the context cache", url))
+        })?;
+        let context = fallback.load(url).await?;
+        self.contexts
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), context.clone());
+        Ok(context)
+    }
+}
+
+impl Graph {
+    /// Read a JSON-LD document retrieved from `base`, loading remote contexts with `loader`.
+    pub async fn from_json_ld(
+        body: &[u8],
+        base: Option<&str>,
+        loader: &(impl DocumentLoader + Sync + ?Sized),
+    ) -> Result<Graph, LookupError> {
+        let document: Json = serde_json::from_slice(body).map_err(json_error)?;
+        let contexts = load_contexts(&document, base, loader).await?;
+        let mut reader = Reader {
+            contexts: &contexts,
+            base,
+            blanks: BTreeMap::new(),
+            triples: Vec::new(),
+        };
+        let context = Context {
+            base: base.map(str::to_string),
+            ..Context::default()
+        };
+        match &document {
+            Json::Object(node) => {
+                reader.node(&context, node)?;
+            }
+            Json::Array(nodes) => {
+                for node in nodes {
+                    match node {
+                        Json::Object(node) => {
+                            reader.node(&context, node)?;
+                        }
+                        _ => return Err(invalid("top level values must be node objects")),
+                    }
+                }
+            }
+            _ => return Err(invalid("a document must be an object or an array")),
+        }
+        Ok(Graph::new(reader.triples.into_iter()))
+    }
+}
+
+/// Retrieve every remote context `document` depends on, directly or through other contexts.
+async fn load_contexts(
+    document: &Json,
+    base: Option<&str>,
+    loader: &(impl DocumentLoader + Sync + ?Sized),
+) -> Result<BTreeMap<String, Json>, LookupError> {
+    let mut loaded = BTreeMap::new();
+    let mut pending = Vec::new();
+    remote_contexts(document, base, &mut pending);
+    while let Some(url) = pending.pop() {
+        if loaded.contains_key(&url) {
+            continue;
+        }
+        if loaded.len() >= MAX_REMOTE_CONTEXTS {
+            return Err(invalid("too many remote contexts"));
+        }
+        let context = loader.load(&url).await?;
+        remote_contexts(&context, Some(&url), &mut pending);
+        loaded.insert(url, context);
+    }
+    Ok(loaded)
+}
+
+/// The urls of remote contexts mentioned anywhere in `json`, resolved against `base`.
+fn remote_contexts(json: &Json, base: Option<&str>, out: &mut Vec<String>) {
+    match json {
+        Json::Array(items) => {
+            for item in items {
+                remote_contexts(item, base, out);
+            }
+        }
+        Json::Object(object) => {
+            for (key, value) in object {
+                if key == "@context" {
+                    match value {
+                        Json::String(url) => out.push(resolve(base, url)),
+                        Json::Array(items) => out.extend(
+                            items
+                                .iter()
+                                .filter_map(Json::as_str)
+                                .map(|url| resolve(base, url)),
+                        ),
+                        _ => {}
+                    }
+                }
+                remote_contexts(value, base, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[derive(Clone, Default)]
+struct Context {
+    base: Option<String>,
+    vocab: Option<String>,
+    language: Option<String>,
+    /// A term mapped to None has been explicitly undefined.
+    terms: BTreeMap<String, Option<Definition>>,
+}
+
+#[derive(Clone, Default)]
+struct Definition {
+    /// An iri or a keyword.
+    id: String,
+    reverse: bool,
+    prefix: bool,
+    /// `@id`, `@vocab`, `@json`, `@none` or a datatype iri.
+    coerce: Option<String>,
+    container: Vec<String>,
+    /// Some(None) means strings have no language even if the context has a default.
+    language: Option<Option<String>>,
+    context: Option<(Json, Option<String>)>,
+}
+
+impl Definition {
+    fn container(&self, kind: &str) -> bool {
+        self.container.iter().any(|c| c == kind)
+    }
+}
+
+struct Reader<'a> {
+    contexts: &'a BTreeMap<String, Json>,
+    base: Option<&'a str>,
+    blanks: BTreeMap<String, om::BlankNode>,
+    triples: Vec<om::Triple>,
+}
+
+impl<'a> Reader<'a> {
+    fn process_context(
+        &self,
+        active: &Context,
+        local: &Json,
+        url_base: Option<&str>,
+        depth: usize,
+    ) -> Result<Context, LookupError> {
+        if depth > MAX_CONTEXT_DEPTH {
+            return Err(invalid("contexts are nested too deeply"));
+        }
+        let mut result = active.clone();
+        let items = match local {
+            Json::Array(items) => items.iter().collect(),
+            other => vec![other],
+        };
+        for item in items {
+            match item {
+                Json::Null => {
+                    result = Context {
+                        base: self.base.map(str::to_string),
+                        ..Context::default()
+                    };
+                }
+                Json::String(url) => {
+                    let url = resolve(url_base, url);
+                    let context = self
+                        .contexts
+                        .get(&url)
+                        .and_then(|document| document.get("@context"))
+                        .ok_or_else(|| invalid(format!("{} is not a context", url)))?;
+                    result = self.process_context(&result, context, Some(&url), depth + 1)?;
+                }
+                Json::Object(local) => {
+                    if let Some(base) = local.get("@base") {
+                        result.base = match base {
+                            Json::String(base) => Some(resolve(result.base.as_deref(), base)),
+                            _ => None,
+                        };
+                    }
+                    let mut defined = BTreeMap::new();
+                    if let Some(vocab) = local.get("@vocab") {
+                        result.vocab = match vocab {
+                            Json::String(vocab) => {
+                                self.dependencies(
+                                    &mut result,
+                                    local,
+                                    &mut defined,
+                                    vocab,
+                                    url_base,
+                                )?;
+                                expand_iri(&result, vocab, true, true)
+                            }
+                            _ => None,
+                        };
+                    }
+                    if let Some(language) = local.get("@language") {
+                        result.language = language.as_str().map(str::to_ascii_lowercase);
+                    }
+                    for term in local.keys() {
+                        self.define(&mut result, local, term, &mut defined, url_base)?;
+                    }
+                }
+                _ => return Err(invalid("a context must be an object, a url or null")),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Create the definition of `term` from the local context it appears in.
+    fn define(
+        &self,
+        context: &mut Context,
+        local: &Map<String, Json>,
+        term: &str,
+        defined: &mut BTreeMap<String, bool>,
+        url_base: Option<&str>,
+    ) -> Result<(), LookupError> {
+        match defined.get(term) {
+            Some(true) => return Ok(()),
+            Some(false) => return Err(invalid(format!("cyclic definition of {}", term))),
+            None => {}
+        }
+        if is_keyword(term) {
+            // Context keywords are handled by process_context and keywords can't be redefined.
+            defined.insert(term.to_string(), true);
+            return Ok(());
+        }
+        defined.insert(term.to_string(), false);
+        let value = match &local[term] {
+            Json::Null => Map::new().tap_mut(|m| {
+                m.insert("@id".into(), Json::Null);
+            }),
+            Json::String(id) => Map::new().tap_mut(|m| {
+                m.insert("@id".into(), Json::String(id.clone()));
+            }),
+            Json::Object(value) => value.clone(),
+            _ => return Err(invalid(format!("invalid definition of {}", term))),
+        };
+        let simple = local[term].is_string();
+
+        let mut definition = Definition::default();
+        if let Some(reverse) = value.get("@reverse") {
+            let reverse = reverse
+                .as_str()
+                .ok_or_else(|| invalid(format!("invalid @reverse for {}", term)))?;
+            self.dependencies(context, local, defined, reverse, url_base)?;
+            definition.id = expand_iri(context, reverse, false, true)
+                .ok_or_else(|| invalid(format!("invalid @reverse for {}", term)))?;
+            definition.reverse = true;
+        } else {
+            match value.get("@id") {
+                Some(Json::Null) => {
+                    context.terms.insert(term.to_string(), None);
+                    defined.insert(term.to_string(), true);
+                    return Ok(());
+                }
+                Some(Json::String(id)) if id != term => {
+                    self.dependencies(context, local, defined, id, url_base)?;
+                    definition.id = expand_iri(context, id, false, true)
+                        .ok_or_else(|| invalid(format!("invalid @id for {}", term)))?;
+                    definition.prefix = simple && definition.id.ends_with(GEN_DELIMS);
+                }
+                Some(Json::String(_)) | None => {
+                    definition.id = match term.split_once(':') {
+                        Some((prefix, suffix)) if !prefix.is_empty() => {
+                            if local.contains_key(prefix) {
+                                self.define(context, local, prefix, defined, url_base)?;
+                            }
+                            match context.terms.get(prefix) {
+                                Some(Some(prefix)) => format!("{}{}", prefix.id, suffix),
+                                _ => term.to_string(),
+                            }
+                        }
+                        _ => match &context.vocab {
+                            Some(vocab) => format!("{}{}", vocab, term),
+                            None => return Err(invalid(format!("no iri mapping for {}", term))),
+                        },
+                    };
+                }
+                Some(_) => return Err(invalid(format!("invalid @id for {}", term))),
+            }
+        }
+        if let Some(coerce) = value.get("@type").and_then(Json::as_str) {
+            definition.coerce = match coerce {
+                "@id" | "@vocab" | "@json" | "@none" => Some(coerce.to_string()),
+                _ => {
+                    self.dependencies(context, local, defined, coerce, url_base)?;
+                    expand_iri(context, coerce, false, true)
+                }
+            };
+        }
+        match value.get("@container") {
+            Some(Json::String(container)) => definition.container = vec![container.clone()],
+            Some(Json::Array(containers)) => {
+                definition.container = containers
+                    .iter()
+                    .filter_map(Json::as_str)
+                    .map(str::to_string)
+                    .collect()
+            }
+            _ => {}
+        }
+        if let Some(prefix) = value.get("@prefix").and_then(Json::as_bool) {
+            definition.prefix = prefix;
+        }
+        if let Some(language) = value.get("@language") {
+            definition.language = Some(language.as_str().map(str::to_ascii_lowercase));
+        }
+        if let Some(scoped) = value.get("@context") {
+            definition.context = Some((scoped.clone(), url_base.map(str::to_string)));
+        }
+        context.terms.insert(term.to_string(), Some(definition));
+        defined.insert(term.to_string(), true);
+        Ok(())
+    }
+
+    /// Define the terms in the local context that expanding `value` depends on.
+    fn dependencies(
+        &self,
+        context: &mut Context,
+        local: &Map<String, Json>,
+        defined: &mut BTreeMap<String, bool>,
+        value: &str,
+        url_base: Option<&str>,
+    ) -> Result<(), LookupError> {
+        if local.contains_key(value) {
+            self.define(context, local, value, defined, url_base)?;
+        }
+        if let Some((prefix, _)) = value.split_once(':') {
+            if local.contains_key(prefix) {
+                self.define(context, local, prefix, defined, url_base)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Emit the triples described by a node object and return its subject, or None if the
+    /// subject is not a valid iri.
+    fn node(
+        &mut self,
+        active: &Context,
+        node: &Map<String, Json>,
+    ) -> Result<Option<om::NamedOrBlankNode>, LookupError> {
+        let mut context = active.clone();
+        if let Some(local) = node.get("@context") {
+            context = self.process_context(&context, local, self.base, 0)?;
+        }
+
+        // Contexts scoped to the node's types apply to its own properties but not to nested
+        // nodes.
+        let mut types: Vec<&str> = node
+            .iter()
+            .filter(|(key, _)| expand_iri(&context, key, false, true).as_deref() == Some("@type"))
+            .flat_map(|(_, value)| as_array(value))
+            .filter_map(Json::as_str)
+            .collect();
+        types.sort_unstable();
+        let mut typed = context.clone();
+        for ty in &types {
+            if let Some(Some(Definition {
+                context: Some((scoped, url_base)),
+                ..
+            })) = context.terms.get(*ty)
+            {
+                typed = self.process_context(&typed, scoped, url_base.as_deref(), 0)?;
+            }
+        }
+
+        let subject = match node
+            .iter()
+            .find(|(key, _)| expand_iri(&typed, key, false, true).as_deref() == Some("@id"))
+            .and_then(|(_, id)| id.as_str())
+        {
+            Some(id) => expand_iri(&typed, id, true, false).and_then(|id| self.subject(&id)),
+            None => Some(om::BlankNode::default().into()),
+        };
+        for ty in types {
+            let ty = expand_iri(&context, ty, true, true).and_then(|ty| self.iri(&ty));
+            self.emit(&subject, &format!("{}type", RDF), ty);
+        }
+        self.properties(&typed, &context, &subject, node)?;
+        Ok(subject)
+    }
+
+    /// Emit the properties of a node. Keys and values are expanded with `active`; nested nodes
+    /// are expanded with `nested`.
+    fn properties(
+        &mut self,
+        active: &Context,
+        nested: &Context,
+        subject: &Option<om::NamedOrBlankNode>,
+        node: &Map<String, Json>,
+    ) -> Result<(), LookupError> {
+        for (key, value) in node {
+            if key == "@context" {
+                continue;
+            }
+            let property = match expand_iri(active, key, false, true) {
+                Some(property) => property,
+                None => continue,
+            };
+            match property.as_str() {
+                "@graph" | "@included" => {
+                    for node in as_array(value).iter().filter_map(|v| v.as_object()) {
+                        self.node(nested, node)?;
+                    }
+                }
+                "@nest" => {
+                    for nest in as_array(value).iter().filter_map(|v| v.as_object()) {
+                        self.properties(active, nested, subject, nest)?;
+                    }
+                }
+                "@reverse" => {
+                    let reverse = value
+                        .as_object()
+                        .ok_or_else(|| invalid("@reverse must be an object"))?;
+                    for (key, value) in reverse {
+                        let property = match expand_iri(active, key, false, true) {
+                            Some(property) if !is_keyword(&property) => property,
+                            _ => continue,
+                        };
+                        for node in as_array(value).iter().filter_map(|v| v.as_object()) {
+                            let object = self.node(nested, node)?;
+                            let subject = subject.clone().map(Into::into);
+                            self.emit(&object, &property, subject);
+                        }
+                    }
+                }
+                _ if is_keyword(&property) => {}
+                _ if !property.contains(':') => {}
+                _ => {
+                    let definition = active.terms.get(key).cloned().flatten();
+                    if matches!(&definition, Some(d) if d.container("@graph")) {
+                        continue;
+                    }
+                    let nested = match &definition {
+                        Some(Definition {
+                            context: Some((scoped, url_base)),
+                            ..
+                        }) => self.process_context(nested, scoped, url_base.as_deref(), 0)?,
+                        _ => nested.clone(),
+                    };
+                    let mut objects = Vec::new();
+                    self.values(active, &nested, definition.as_ref(), value, &mut objects)?;
+                    for object in objects {
+                        if matches!(definition, Some(Definition { reverse: true, .. })) {
+                            let object = match object {
+                                om::Term::NamedNode(nn) => Some(nn.into()),
+                                om::Term::BlankNode(bn) => Some(bn.into()),
+                                om::Term::Literal(_) => None,
+                            };
+                            let subject = subject.clone().map(Into::into);
+                            self.emit(&object, &property, subject);
+                        } else {
+                            self.emit(subject, &property, Some(object));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Expand the value of a property into rdf terms.
+    fn values(
+        &mut self,
+        active: &Context,
+        nested: &Context,
+        definition: Option<&Definition>,
+        value: &Json,
+        out: &mut Vec<om::Term>,
+    ) -> Result<(), LookupError> {
+        let container = |kind| definition.filter(|d| d.container(kind)).is_some();
+        let json = definition.and_then(|d| d.coerce.as_deref()) == Some("@json");
+        match value {
+            // A JSON literal is the whole value, arrays and objects included.
+            _ if json => self.value(active, nested, definition, value, out)?,
+            Json::Object(map) if container("@language") => {
+                for (language, strings) in map {
+                    for string in as_array(strings).iter().filter_map(|s| s.as_str()) {
+                        out.push(if language == "@none" {
+                            om::Literal::new_simple_literal(string).into()
+                        } else {
+                            language_literal(string, language)?
+                        });
+                    }
+                }
+            }
+            Json::Object(map) if container("@index") => {
+                for item in map.values().flat_map(as_array) {
+                    self.value(active, nested, definition, item, out)?;
+                }
+            }
+            Json::Object(map) if container("@id") || container("@type") => {
+                let coerce = definition.and_then(|d| d.coerce.as_deref());
+                for (key, items) in map {
+                    for item in as_array(items) {
+                        let mut node = match item {
+                            Json::Object(node) => node.clone(),
+                            Json::String(id) if container("@type") => {
+                                let id = expand_iri(active, id, true, coerce == Some("@vocab"));
+                                Map::new().tap_mut(|m| {
+                                    m.insert("@id".into(), id.map_or(Json::Null, Json::String));
+                                })
+                            }
+                            _ => return Err(invalid("values of id and type maps must be nodes")),
+                        };
+                        let has_id = node.keys().any(|key| {
+                            expand_iri(nested, key, false, true).as_deref() == Some("@id")
+                        });
+                        if key == "@none" {
+                            // The node is left as it is.
+                        } else if container("@id") && !has_id {
+                            let id = expand_iri(active, key, true, false);
+                            node.insert("@id".into(), id.map_or(Json::Null, Json::String));
+                        } else if container("@type") {
+                            // The key is not expanded here so that contexts scoped to the type
+                            // still apply to the node.
+                            let mut types = vec![Json::String(key.clone())];
+                            if let Some(ty) = node.remove("@type") {
+                                types.extend(as_array(&ty).iter().cloned());
+                            }
+                            node.insert("@type".into(), Json::Array(types));
+                        }
+                        self.value(active, nested, definition, &Json::Object(node), out)?;
+                    }
+                }
+            }
+            Json::Array(items) if container("@list") => {
+                let list = self.list(active, nested, definition, items)?;
+                out.push(list);
+            }
+            Json::Array(items) => {
+                for item in items {
+                    self.values(active, nested, definition, item, out)?;
+                }
+            }
+            _ if container("@list") && !self.is_list_object(active, value) => {
+                let list = self.list(active, nested, definition, core::slice::from_ref(value))?;
+                out.push(list);
+            }
+            _ => self.value(active, nested, definition, value, out)?,
+        }
+        Ok(())
+    }
+
+    fn value(
+        &mut self,
+        active: &Context,
+        nested: &Context,
+        definition: Option<&Definition>,
+        value: &Json,
+        out: &mut Vec<om::Term>,
+    ) -> Result<(), LookupError> {
+        let coerce = definition.and_then(|d| d.coerce.as_deref());
+        let object = match (value, coerce) {
+            (Json::Null, _) => None,
+            (_, Some("@json")) => Some(json_literal(value)),
+            (Json::String(id), Some("@id")) => {
+                expand_iri(active, id, true, false).and_then(|id| self.iri(&id))
+            }
+            (Json::String(id), Some("@vocab")) => {
+                expand_iri(active, id, true, true).and_then(|id| self.iri(&id))
+            }
+            (Json::String(string), _) => {
+                let language = match definition.and_then(|d| d.language.as_ref()) {
+                    Some(language) => language.as_deref(),
+                    None => active.language.as_deref(),
+                };
+                Some(literal(string, datatype(coerce), language)?)
+            }
+            (Json::Bool(_), _) | (Json::Number(_), _) => Some(native(value, datatype(coerce))),
+            (Json::Array(_), _) => {
+                return self.values(active, nested, definition, value, out);
+            }
+            (Json::Object(object), _) => {
+                let keyword = |kw: &str| {
+                    object
+                        .iter()
+                        .find(|(key, _)| {
+                            expand_iri(active, key, false, true).as_deref() == Some(kw)
+                        })
+                        .map(|(_, value)| value)
+                };
+                if let Some(value) = keyword("@value") {
+                    let ty = keyword("@type").and_then(Json::as_str);
+                    let language = keyword("@language").and_then(Json::as_str);
+                    match (value, ty) {
+                        (Json::Null, _) => None,
+                        (_, Some("@json")) => Some(json_literal(value)),
+                        (Json::String(string), ty) => {
+                            let ty = ty.and_then(|ty| expand_iri(active, ty, true, true));
+                            Some(literal(string, ty.as_deref(), language)?)
+                        }
+                        (Json::Bool(_), ty) | (Json::Number(_), ty) => {
+                            let ty = ty.and_then(|ty| expand_iri(active, ty, true, true));
+                            Some(native(value, ty.as_deref()))
+                        }
+                        _ => return Err(invalid("invalid @value")),
+                    }
+                } else if let Some(items) = keyword("@list") {
+                    Some(self.list(active, nested, definition, as_array(items))?)
+                } else if let Some(values) = keyword("@set") {
+                    return self.values(active, nested, definition, values, out);
+                } else {
+                    self.node(nested, object)?.map(Into::into)
+                }
+            }
+        };
+        out.extend(object);
+        Ok(())
+    }
+
+    /// Emit an rdf list and return its head.
+    fn list(
+        &mut self,
+        active: &Context,
+        nested: &Context,
+        definition: Option<&Definition>,
+        items: &[Json],
+    ) -> Result<om::Term, LookupError> {
+        let mut members = Vec::new();
+        for item in items {
+            self.value(active, nested, definition, item, &mut members)?;
+        }
+        let mut head: om::Term = om::NamedNode::new_unchecked(format!("{}nil", RDF)).into();
+        for member in members.into_iter().rev() {
+            let node = om::BlankNode::default();
+            let subject = Some(node.clone().into());
+            self.emit(&subject, &format!("{}first", RDF), Some(member));
+            self.emit(&subject, &format!("{}rest", RDF), Some(head));
+            head = node.into();
+        }
+        Ok(head)
+    }
+
+    fn is_list_object(&self, active: &Context, value: &Json) -> bool {
+        value
+            .as_object()
+            .into_iter()
+            .flat_map(|object| object.keys())
+            .any(|key| expand_iri(active, key, false, true).as_deref() == Some("@list"))
+    }
+
+    fn emit(
+        &mut self,
+        subject: &Option<om::NamedOrBlankNode>,
+        predicate: &str,
+        object: Option<om::Term>,
+    ) {
+        if let (Some(subject), Ok(predicate), Some(object)) =
+            (subject, om::NamedNode::new(predicate), object)
+        {
+            self.triples
+                .push(om::Triple::new(subject.clone(), predicate, object));
+        }
+    }
+
+    fn subject(&mut self, iri: &str) -> Option<om::NamedOrBlankNode> {
+        self.iri(iri).and_then(|term| match term {
+            om::Term::NamedNode(nn) => Some(nn.into()),
+            om::Term::BlankNode(bn) => Some(bn.into()),
+            om::Term::Literal(_) => None,
+        })
+    }
+
+    /// A named node, or a blank node if `iri` is a blank node identifier. None if `iri` is
+    /// relative or otherwise invalid.
+    fn iri(&mut self, iri: &str) -> Option<om::Term> {
+        match iri.strip_prefix("_:") {
+            Some(label) => Some(
+                self.blanks
+                    .entry(label.to_string())
+                    .or_default()
+                    .clone()
+                    .into(),
+            ),
+            None => om::NamedNode::new(iri).ok().map(Into::into),
+        }
+    }
+}
+
+const GEN_DELIMS: &[char] = &[':', '/', '?', '#', '[', ']', '@'];
+
+/// Expand a term, compact iri or relative iri against `context`. Returns None for terms that
+/// have been explicitly undefined.
+fn expand_iri(
+    context: &Context,
+    value: &str,
+    document_relative: bool,
+    vocab: bool,
+) -> Option<String> {
+    if is_keyword(value) {
+        return Some(value.to_string());
+    }
+    if vocab {
+        if let Some(definition) = context.terms.get(value) {
+            return definition.as_ref().map(|d| d.id.clone());
+        }
+    }
+    if let Some((prefix, suffix)) = value.split_once(':') {
+        if prefix == "_" || suffix.starts_with("//") {
+            return Some(value.to_string());
+        }
+        if let Some(Some(definition)) = context.terms.get(prefix) {
+            if definition.prefix {
+                return Some(format!("{}{}", definition.id, suffix));
+            }
+        }
+        return Some(value.to_string());
+    }
+    if vocab {
+        if let Some(vocab) = &context.vocab {
+            return Some(format!("{}{}", vocab, value));
+        }
+    }
+    if document_relative {
+        return Some(resolve(context.base.as_deref(), value));
+    }
+    Some(value.to_string())
+}
+
+fn resolve(base: Option<&str>, iri: &str) -> String {
+    base.and_then(|base| oxiri::Iri::parse(base).ok())
+        .and_then(|base| base.resolve(iri).ok())
+        .map(|iri| iri.into_inner())
+        .unwrap_or_else(|| iri.to_string())
+}
+
+fn is_keyword(value: &str) -> bool {
+    value.len() > 1 && value.starts_with('@') && value[1..].chars().all(|c| c.is_ascii_alphabetic())
+}
+
+fn as_array(value: &Json) -> &[Json] {
+    match value {
+        Json::Array(items) => items,
+        other => core::slice::from_ref(other),
+    }
+}
+
+fn datatype(coerce: Option<&str>) -> Option<&str> {
+    coerce.filter(|c| !is_keyword(c))
+}
+
+fn literal(
+    value: &str,
+    datatype: Option<&str>,
+    language: Option<&str>,
+) -> Result<om::Term, LookupError> {
+    Ok(match (datatype, language) {
+        (Some(datatype), _) => {
+            let datatype = om::NamedNode::new(datatype)
+                .map_err(|e| invalid(format!("invalid datatype {}: {}", datatype, e)))?;
+            om::Literal::new_typed_literal(value, datatype).into()
+        }
+        (None, Some(language)) => language_literal(value, language)?,
+        (None, None) => om::Literal::new_simple_literal(value).into(),
+    })
+}
+
+fn language_literal(value: &str, language: &str) -> Result<om::Term, LookupError> {
+    om::Literal::new_language_tagged_literal(value, language)
+        .map(Into::into)
+        .map_err(|e| invalid(format!("invalid language {}: {}", language, e)))
+}
+
+/// A boolean or number, in canonical form.
+fn native(value: &Json, datatype: Option<&str>) -> om::Term {
+    let double = datatype == Some(&format!("{}double", XSD));
+    let (lexical, default) = match value {
+        Json::Bool(b) => (b.to_string(), "boolean"),
+        Json::Number(n) if (n.is_i64() || n.is_u64()) && !double => (n.to_string(), "integer"),
+        // Numbers without a fractional part are integers however they are written, e.g. `1.0`.
+        Json::Number(n) => match n.as_f64().unwrap_or_default() {
+            d if d.fract() == 0.0 && d.abs() < 1e21 && !double => (format!("{:.0}", d), "integer"),
+            d => (canonical_double(d), "double"),
+        },
+        _ => unreachable!("only booleans and numbers are native"),
+    };
+    let datatype = match datatype {
+        Some(datatype) => datatype.to_string(),
+        None => format!("{}{}", XSD, default),
+    };
+    om::Literal::new_typed_literal(lexical, om::NamedNode::new_unchecked(datatype)).into()
+}
+
+/// Format a double as JSON-LD requires, e.g. `1.5E1`.
+fn canonical_double(d: f64) -> String {
+    let formatted = format!("{:E}", d);
+    match formatted.split_once('E') {
+        Some((mantissa, exponent)) if !mantissa.contains('.') => {
+            format!("{}.0E{}", mantissa, exponent)
+        }
+        _ => formatted,
+    }
+}
+
+fn json_literal(value: &Json) -> om::Term {
+    om::Literal::new_typed_literal(
+        value.to_string(),
+        om::NamedNode::new_unchecked(format!("{}JSON", RDF)),
+    )
+    .into()
+}
+
+/// Convert a JSON syntax error, keeping its position.
+pub(crate) fn json_error(err: serde_json::Error) -> LookupError {
+    LookupError::Parse {
+        message: err.to_string(),
+        line: Some(err.line() as u64),
+        column: Some(err.column() as u64),
+    }
+}
+
+fn invalid(message: impl Into<String>) -> LookupError {
+    LookupError::Parse {
+        message: format!("invalid JSON-LD: {}", message.into()),
+        line: None,
+        column: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::executor::block_on;
+    use serde_json::json;
+
+    const CREDENTIALS: &str = "https://www.w3.org/2018/credentials/v1";
+
+    /// A cut down version of the verifiable credentials context.
+    fn credentials_context() -> Json {
+        json!({
+            "@context": {
+                "@version": 1.1,
+                "@protected": true,
+                "id": "@id",
+                "type": "@type",
+                "VerifiableCredential": {
+                    "@id": "https://www.w3.org/2018/credentials#VerifiableCredential",
+                    "@context": {
+                        "@version": 1.1,
+                        "@protected": true,
+                        "id": "@id",
+                        "type": "@type",
+                        "cred": "https://www.w3.org/2018/credentials#",
+                        "xsd": "http://www.w3.org/2001/XMLSchema#",
+                        "credentialSubject": {"@id": "cred:credentialSubject", "@type": "@id"},
+                        "issuer": {"@id": "cred:issuer", "@type": "@id"},
+                        "issuanceDate": {"@id": "cred:issuanceDate", "@type": "xsd:dateTime"}
+                    }
+                }
+            }
+        })
+    }
+
+    fn read(document: Json, loader: &ContextCache) -> Result<Vec<String>, LookupError> {
+        let body = document.to_string();
+        let graph = block_on(Graph::from_json_ld(
+            body.as_bytes(),
+            Some("http://example.com/doc"),
+            loader,
+        ))?;
+        Ok(ntriples(graph))
+    }
+
+    /// The graph as sorted N-Triples, with blank node labels erased.
+    fn ntriples(graph: Graph) -> Vec<String> {
+        let mut ret: Vec<String> = graph
+            .into_iter()
+            .map(|t| {
+                t.to_string()
+                    .split(' ')
+                    .map(|term| if term.starts_with("_:") { "_:" } else { term })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        ret.sort();
+        ret
+    }
+
+    fn sorted(triples: &[&str]) -> Vec<String> {
+        let mut ret: Vec<String> = triples.iter().map(|t| t.to_string()).collect();
+        ret.sort();
+        ret
+    }
+
+    #[test]
+    fn embedded_context() {
+        let document = json!({
+            "@context": {
+                "@vocab": "http://schema.org/",
+                "@language": "en",
+                "ex": "http://example.com/",
+                "id": "@id",
+                "knows": {"@type": "@id"},
+                "age": {"@id": "ex:age", "@type": "http://www.w3.org/2001/XMLSchema#integer"},
+                "nick": {"@language": null},
+                "children": {"@container": "@list"},
+                "label": {"@container": "@language"},
+                "parent": {"@reverse": "children"},
+                "ignored": null
+            },
+            "id": "ex:alice",
+            "@type": "Person",
+            "name": "Alice",
+            "nick": "al",
+            "knows": "bob",
+            "age": "42",
+            "height": 1.5,
+            "verified": true,
+            "children": ["ex:carol", {"@id": "ex:dave"}],
+            "label": {"fr": "Alice"},
+            "parent": {"@id": "ex:erin"},
+            "ignored": "nothing",
+            "nested": {"name": {"@value": "Frank", "@language": "de"}}
+        });
+        assert_eq!(
+            read(document, &ContextCache::new()),
+            Ok(sorted(&[
+                "<http://example.com/alice> <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <http://schema.org/Person> .",
+                "<http://example.com/alice> <http://schema.org/name> \"Alice\"@en .",
+                "<http://example.com/alice> <http://schema.org/nick> \"al\" .",
+                "<http://example.com/alice> <http://schema.org/knows> <http://example.com/bob> .",
+                "<http://example.com/alice> <http://example.com/age> \"42\"^^<http://www.w3.org/2001/XMLSchema#integer> .",
+                "<http://example.com/alice> <http://schema.org/height> \"1.5E0\"^^<http://www.w3.org/2001/XMLSchema#double> .",
+                "<http://example.com/alice> <http://schema.org/verified> \"true\"^^<http://www.w3.org/2001/XMLSchema#boolean> .",
+                "<http://example.com/alice> <http://schema.org/children> _: .",
+                "_: <http://www.w3.org/1999/02/22-rdf-syntax-ns#first> \"ex:carol\"@en .",
+                "_: <http://www.w3.org/1999/02/22-rdf-syntax-ns#rest> _: .",
+                "_: <http://www.w3.org/1999/02/22-rdf-syntax-ns#first> <http://example.com/dave> .",
+                "_: <http://www.w3.org/1999/02/22-rdf-syntax-ns#rest> <http://www.w3.org/1999/02/22-rdf-syntax-ns#nil> .",
+                "<http://example.com/alice> <http://schema.org/label> \"Alice\"@fr .",
+                "<http://example.com/erin> <http://schema.org/children> <http://example.com/alice> .",
+                "<http://example.com/alice> <http://schema.org/nested> _: .",
+                "_: <http://schema.org/name> \"Frank\"@de .",
+            ]))
+        );
+    }
+
+    #[test]
+    fn remote_type_scoped_context() {
+        let credential = json!({
+            "@context": [CREDENTIALS, {"ex": "http://example.com/vocab#"}],
+            "id": "http://example.com/credentials/1",
+            "type": "VerifiableCredential",
+            "issuer": "did:example:issuer",
+            "issuanceDate": "2021-01-01T00:00:00Z",
+            "credentialSubject": {
+                "id": "did:example:subject",
+                "ex:degree": "BSc",
+                "issuer": "not a term outside the credential"
+            }
+        });
+        let contexts = ContextCache::new().with_context(CREDENTIALS, credentials_context());
+        assert_eq!(
+            read(credential.clone(), &contexts),
+            Ok(sorted(&[
+                "<http://example.com/credentials/1> <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <https://www.w3.org/2018/credentials#VerifiableCredential> .",
+                "<http://example.com/credentials/1> <https://www.w3.org/2018/credentials#issuer> <did:example:issuer> .",
+                "<http://example.com/credentials/1> <https://www.w3.org/2018/credentials#issuanceDate> \"2021-01-01T00:00:00Z\"^^<http://www.w3.org/2001/XMLSchema#dateTime> .",
+                "<http://example.com/credentials/1> <https://www.w3.org/2018/credentials#credentialSubject> <did:example:subject> .",
+                "<did:example:subject> <http://example.com/vocab#degree> \"BSc\" .",
+            ]))
+        );
+        assert_eq!(
+            read(credential, &ContextCache::new()),
+            Err(LookupError::Refused(format!(
+                "context {} is not in the context cache",
+                CREDENTIALS
+            )))
+        );
+    }
+
+    /// Cases adapted from the W3C JSON-LD 1.1 toRdf test suite, as a document and the triples
+    /// it reads as. `ex:` abbreviates `http://example.org/`.
+    #[test]
+    fn to_rdf() {
+        let cases = vec![
+            (
+                "@graph",
+                json!({
+                    "@context": {"@vocab": "http://example.org/"},
+                    "@id": "http://example.org/g",
+                    "@graph": [
+                        {"@id": "http://example.org/a", "p": "x"},
+                        {"@id": "http://example.org/b", "p": "y"}
+                    ]
+                }),
+                vec!["<ex:a> <ex:p> \"x\" .", "<ex:b> <ex:p> \"y\" ."],
+            ),
+            (
+                "graph container",
+                json!({
+                    "@context": {
+                        "@vocab": "http://example.org/",
+                        "proof": {"@container": "@graph"}
+                    },
+                    "@id": "http://example.org/a",
+                    "claim": "x",
+                    "proof": {"signature": "y"}
+                }),
+                vec!["<ex:a> <ex:claim> \"x\" ."],
+            ),
+            (
+                "@included",
+                json!({
+                    "@context": {"@version": 1.1, "@vocab": "http://example.org/"},
+                    "@id": "http://example.org/a",
+                    "p": "x",
+                    "@included": [{"@id": "http://example.org/b", "p": "y"}]
+                }),
+                vec!["<ex:a> <ex:p> \"x\" .", "<ex:b> <ex:p> \"y\" ."],
+            ),
+            (
+                "@nest",
+                json!({
+                    "@context": {"@vocab": "http://example.org/", "nested": "@nest"},
+                    "@id": "http://example.org/a",
+                    "nested": {"p": "x", "@nest": {"q": "y"}},
+                    "@nest": [{"r": "z"}]
+                }),
+                vec![
+                    "<ex:a> <ex:p> \"x\" .",
+                    "<ex:a> <ex:q> \"y\" .",
+                    "<ex:a> <ex:r> \"z\" .",
+                ],
+            ),
+            (
+                "@reverse",
+                json!({
+                    "@context": {
+                        "@vocab": "http://example.org/",
+                        "knownBy": {"@reverse": "http://example.org/knows"}
+                    },
+                    "@id": "http://example.org/a",
+                    "@reverse": {"knows": {"@id": "http://example.org/b"}},
+                    "knownBy": [{"@id": "http://example.org/c", "name": "C"}]
+                }),
+                vec![
+                    "<ex:b> <ex:knows> <ex:a> .",
+                    "<ex:c> <ex:knows> <ex:a> .",
+                    "<ex:c> <ex:name> \"C\" .",
+                ],
+            ),
+            (
+                "@list",
+                json!({
+                    "@context": {
+                        "@vocab": "http://example.org/",
+                        "ordered": {"@container": "@list"}
+                    },
+                    "@id": "http://example.org/a",
+                    "empty": {"@list": []},
+                    "ordered": ["x", {"@id": "http://example.org/b"}]
+                }),
+                vec![
+                    "<ex:a> <ex:empty> <rdf:nil> .",
+                    "<ex:a> <ex:ordered> _: .",
+                    "_: <rdf:first> \"x\" .",
+                    "_: <rdf:rest> _: .",
+                    "_: <rdf:first> <ex:b> .",
+                    "_: <rdf:rest> <rdf:nil> .",
+                ],
+            ),
+            (
+                "@json",
+                json!({
+                    "@context": {
+                        "@version": 1.1,
+                        "e": {"@id": "http://example.org/e", "@type": "@json"}
+                    },
+                    "@id": "http://example.org/a",
+                    "e": [{"foo": [1, true, null], "bar": "x"}]
+                }),
+                vec![r#"<ex:a> <ex:e> "[{\"bar\":\"x\",\"foo\":[1,true,null]}]"^^<rdf:JSON> ."#],
+            ),
+            (
+                "language map",
+                json!({
+                    "@context": {
+                        "label": {"@id": "http://example.org/label", "@container": "@language"}
+                    },
+                    "@id": "http://example.org/a",
+                    "label": {"en": "The Queen", "de": ["Die Königin", "Ihre Majestät"], "@none": "Q"}
+                }),
+                vec![
+                    "<ex:a> <ex:label> \"The Queen\"@en .",
+                    "<ex:a> <ex:label> \"Die Königin\"@de .",
+                    "<ex:a> <ex:label> \"Ihre Majestät\"@de .",
+                    "<ex:a> <ex:label> \"Q\" .",
+                ],
+            ),
+            (
+                "index map",
+                json!({
+                    "@context": {
+                        "post": {"@id": "http://example.org/post", "@container": "@index"}
+                    },
+                    "@id": "http://example.org/a",
+                    "post": {"en": {"@id": "http://example.org/p1"}, "de": ["x", {"@id": "http://example.org/p2"}]}
+                }),
+                vec![
+                    "<ex:a> <ex:post> <ex:p1> .",
+                    "<ex:a> <ex:post> \"x\" .",
+                    "<ex:a> <ex:post> <ex:p2> .",
+                ],
+            ),
+            (
+                "id map",
+                json!({
+                    "@context": {
+                        "@vocab": "http://example.org/",
+                        "@base": "http://example.org/",
+                        "idmap": {"@container": "@id"}
+                    },
+                    "@id": "http://example.org/a",
+                    "idmap": {
+                        "b": {"name": "B"},
+                        "_:c": {"name": "C"},
+                        "@none": {"name": "D"},
+                        "e": {"@id": "http://example.org/f"}
+                    }
+                }),
+                vec![
+                    "<ex:a> <ex:idmap> <ex:b> .",
+                    "<ex:b> <ex:name> \"B\" .",
+                    "<ex:a> <ex:idmap> _: .",
+                    "_: <ex:name> \"C\" .",
+                    "<ex:a> <ex:idmap> _: .",
+                    "_: <ex:name> \"D\" .",
+                    "<ex:a> <ex:idmap> <ex:f> .",
+                ],
+            ),
+            (
+                "type map",
+                json!({
+                    "@context": {
+                        "@vocab": "http://example.org/",
+                        "typemap": {"@container": "@type"},
+                        "refs": {"@container": "@type", "@type": "@id"},
+                        "Scoped": {"@context": {"name": "http://example.org/scopedName"}}
+                    },
+                    "@id": "http://example.org/a",
+                    "typemap": {
+                        "T": {"@id": "http://example.org/b", "@type": "U"},
+                        "Scoped": {"name": "S"},
+                        "@none": {"name": "N"}
+                    },
+                    "refs": {"T": "http://example.org/c"}
+                }),
+                vec![
+                    "<ex:a> <ex:typemap> <ex:b> .",
+                    "<ex:b> <rdf:type> <ex:T> .",
+                    "<ex:b> <rdf:type> <ex:U> .",
+                    "<ex:a> <ex:typemap> _: .",
+                    "_: <rdf:type> <ex:Scoped> .",
+                    "_: <ex:scopedName> \"S\" .",
+                    "<ex:a> <ex:typemap> _: .",
+                    "_: <ex:name> \"N\" .",
+                    "<ex:a> <ex:refs> <ex:c> .",
+                    "<ex:c> <rdf:type> <ex:T> .",
+                ],
+            ),
+            (
+                "property-scoped context",
+                json!({
+                    "@context": {
+                        "@vocab": "http://example.org/",
+                        "bar": {"@context": {"@vocab": "http://example.com/"}}
+                    },
+                    "@id": "http://example.org/a",
+                    "bar": {"baz": "x", "qux": {"baz": "y"}},
+                    "baz": "z"
+                }),
+                vec![
+                    "<ex:a> <ex:bar> _: .",
+                    "_: <http://example.com/baz> \"x\" .",
+                    "_: <http://example.com/qux> _: .",
+                    "_: <http://example.com/baz> \"y\" .",
+                    "<ex:a> <ex:baz> \"z\" .",
+                ],
+            ),
+            (
+                "@vocab and @base",
+                json!({
+                    "@context": {
+                        "@base": "http://example.org/base/",
+                        "@vocab": "http://example.org/vocab#"
+                    },
+                    "@id": "relative",
+                    "@type": "T",
+                    "p": {"@id": "../other"},
+                    "q": {"@id": "#frag"}
+                }),
+                vec![
+                    "<ex:base/relative> <rdf:type> <ex:vocab#T> .",
+                    "<ex:base/relative> <ex:vocab#p> <ex:other> .",
+                    "<ex:base/relative> <ex:vocab#q> <ex:base/#frag> .",
+                ],
+            ),
+            (
+                "document base",
+                json!({"@id": "other", "http://example.org/p": {"@id": ""}}),
+                vec!["<http://example.com/other> <ex:p> <http://example.com/doc> ."],
+            ),
+            (
+                "numbers",
+                json!({
+                    "@context": {
+                        "@vocab": "http://example.org/",
+                        "xsd": "http://www.w3.org/2001/XMLSchema#",
+                        "d": {"@type": "xsd:double"},
+                        "i": {"@type": "xsd:integer"}
+                    },
+                    "@id": "http://example.org/a",
+                    "int": 10,
+                    "whole": 10.0,
+                    "frac": 5.3,
+                    "neg": -0.5,
+                    "huge": 1e21,
+                    "d": 5,
+                    "i": 5.3,
+                    "typed": {"@value": 1, "@type": "xsd:float"}
+                }),
+                vec![
+                    "<ex:a> <ex:int> \"10\"^^<xsd:integer> .",
+                    "<ex:a> <ex:whole> \"10\"^^<xsd:integer> .",
+                    "<ex:a> <ex:frac> \"5.3E0\"^^<xsd:double> .",
+                    "<ex:a> <ex:neg> \"-5.0E-1\"^^<xsd:double> .",
+                    "<ex:a> <ex:huge> \"1.0E21\"^^<xsd:double> .",
+                    "<ex:a> <ex:d> \"5.0E0\"^^<xsd:double> .",
+                    "<ex:a> <ex:i> \"5.3E0\"^^<xsd:integer> .",
+                    "<ex:a> <ex:typed> \"1\"^^<xsd:float> .",
+                ],
+            ),
+        ];
+        for (name, document, expected) in cases {
+            let expected: Vec<String> = expected
+                .iter()
+                .map(|triple| {
+                    triple
+                        .replace("<ex:", "<http://example.org/")
+                        .replace("<rdf:", &format!("<{}", RDF))
+                        .replace("<xsd:", &format!("<{}", XSD))
+                })
+                .collect();
+            let expected: Vec<&str> = expected.iter().map(String::as_str).collect();
+            assert_eq!(
+                read(document, &ContextCache::new()),
+                Ok(sorted(&expected)),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        let err = block_on(Graph::from_json_ld(
+            b"{\n  \"a\": ",
+            None,
+            &ContextCache::new(),
+        ));
+        assert!(matches!(err, Err(LookupError::Parse { line: Some(2), .. })));
+        let err = block_on(Graph::from_json_ld(b"\"a\"", None, &ContextCache::new()));
+        assert!(matches!(err, Err(LookupError::Parse { line: None, .. })));
+    }
+}
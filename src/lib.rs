@@ -1,12 +1,28 @@
 mod agent;
+mod budget;
+mod cache;
 mod convert;
 mod crawl_progress;
 mod curiosity;
 #[cfg(test)]
 mod delegation_rules;
+mod did;
+mod fetch;
+mod file;
+mod frontier;
+mod http;
+mod ipfs;
+mod jsonld;
 mod lookup;
+mod parse;
+mod provenance;
 mod rdf_graph;
+mod retry;
+mod router;
+mod sparql;
 mod store;
+#[cfg(test)]
+mod test_server;
 mod translate;
 mod ttl;
 mod types;
@@ -15,13 +31,28 @@ mod util;
 extern crate alloc;
 extern crate core;
 
-pub use agent::Agent;
-pub use curiosity::Curiosity;
-pub use lookup::{Lookup, LookupError};
+pub use agent::{Agent, AgentError};
+pub use budget::{CrawlBudget, CrawlOutcome};
+pub use cache::CachingLookup;
+pub use crawl_progress::{CrawlProgress, Failure};
+pub use curiosity::{Curiosity, CuriosityError, CuriousQuery, SHOULD_FETCH};
+pub use did::DidLookup;
+pub use fetch::{Fetch, Fetched, LookupFetch, RawDocument};
+pub use file::FileLookup;
+pub use http::HttpLookup;
+pub use ipfs::IpfsLookup;
+pub use jsonld::{ContextCache, DocumentLoader};
+pub use lookup::{Lookup, LookupError, LookupErrorKind};
+pub use parse::{media_type_for_extension, parse_document, MEDIA_TYPES};
+pub use provenance::{Evidence, Provenance};
 pub use rdf_graph::Graph;
+pub use retry::RetryPolicy;
+pub use router::{Rewrite, RouterLookup};
+pub use sparql::{SparqlStore, SparqlStoreError};
 pub use store::Store;
 
 #[cfg(test)]
+#[allow(clippy::needless_lifetimes)] // As for `impl Lookup for BTreeMap` in lookup.rs.
 mod test {
     use super::*;
     use crate::agent::Agent;
@@ -29,13 +60,14 @@ mod test {
     use crate::rdf_graph::Graph;
     use crate::store::Store;
     use crate::ttl::from_ttl;
-    use crate::util::as_named_node;
-    use alloc::collections::BTreeMap;
+    use alloc::collections::{BTreeMap, BTreeSet};
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst};
     use futures_lite::future::block_on;
     use oxigraph::model as om;
-    use oxigraph::sparql::QuerySolutionIter;
-    use oxigraph::sparql::{algebra::Query, QueryResults};
+    use oxigraph::sparql::{algebra::Query, EvaluationError, QueryResults};
     use oxigraph::store::MemoryStore;
+    use std::sync::Mutex;
+    use std::time::Duration;
     use tap::prelude::*;
 
     #[test]
@@ -45,9 +77,7 @@ mod test {
             ca.investigate(named_node("did:a")).await.unwrap();
             ca.crawl().await.unwrap();
             assert_eq!(
-                list_graphs(&ca)
-                    .map(|term| as_named_node(&term).unwrap().clone().into_string())
-                    .pipe(sorted),
+                graph_names(&ca),
                 [
                     "did:b",
                     "did:c:claims",
@@ -64,6 +94,884 @@ mod test {
         })
     }
 
+    #[test]
+    fn failures_are_recorded() {
+        block_on(async {
+            let mut ca = default_agent();
+            ca.investigate(named_node("did:a")).await.unwrap();
+            ca.crawl().await.unwrap();
+            let failure = ca
+                .progress()
+                .failure(&named_node(
+                    "https://dock.io/rdf/alpha/attestDocumentContent",
+                ))
+                .unwrap();
+            assert_eq!(failure.error, LookupError::NotFound);
+            assert_eq!(failure.attempts, 1);
+            assert!(ca
+                .progress()
+                .failures()
+                .all(|(iri, _)| !graph_names(&ca).iter().any(|g| g == iri)));
+        })
+    }
+
+    #[test]
+    fn transient_failures_are_retried() {
+        block_on(async {
+            let lookup = Flaky {
+                inner: supergraph(),
+                failed: Default::default(),
+            };
+            let mut ca = Agent::new(
+                Curiosity::create(curious_about_everything()).unwrap(),
+                MemoryStore::default(),
+                lookup,
+            )
+            .with_retry(RetryPolicy {
+                initial_backoff: Duration::from_millis(1),
+                ..Default::default()
+            });
+            assert!(ca.investigate(named_node("did:a")).await.is_err());
+            ca.crawl().await.unwrap();
+            assert_eq!(graph_names(&ca).len(), 6);
+            assert!(ca
+                .progress()
+                .failures()
+                .all(|(_, failure)| failure.error == LookupError::NotFound));
+
+            let mut ca = Agent::new(
+                Curiosity::create(curious_about_everything()).unwrap(),
+                MemoryStore::default(),
+                Flaky {
+                    inner: supergraph(),
+                    failed: Default::default(),
+                },
+            )
+            .with_retry(RetryPolicy::never());
+            assert!(ca.investigate(named_node("did:a")).await.is_err());
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::Exhausted);
+            assert_eq!(graph_names(&ca).len(), 0);
+        })
+    }
+
+    /// Fails the first lookup of every document with a transport error.
+    struct Flaky<L> {
+        inner: L,
+        failed: Mutex<BTreeSet<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl<L: Lookup + Sync> Lookup for Flaky<L> {
+        async fn lookup(&self, iri: &om::NamedNode) -> Result<Graph, LookupError> {
+            if self.failed.lock().unwrap().insert(iri.as_str().to_string()) {
+                return Err(LookupError::Transport("connection reset".into()));
+            }
+            self.inner.lookup(iri).await
+        }
+    }
+
+    #[test]
+    fn resume_from_snapshot() {
+        block_on(async {
+            let store = MemoryStore::default();
+            let mut ca = Agent::new(
+                Curiosity::create(curious_about_everything()).unwrap(),
+                store.clone(),
+                supergraph(),
+            )
+            .with_budget(CrawlBudget {
+                max_rounds: Some(2),
+                ..Default::default()
+            });
+            ca.investigate(named_node("did:a")).await.unwrap();
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::MaxRounds);
+            let saved = serde_json::to_string(ca.progress()).unwrap();
+            let visited = graph_names(&ca);
+            drop(ca);
+
+            // Only documents that were not yet visited can be looked up after resuming.
+            let mut unvisited = supergraph();
+            unvisited.retain(|iri, _| !visited.iter().any(|v| v == iri));
+            let progress: CrawlProgress = serde_json::from_str(&saved).unwrap();
+            let mut ca = Agent::resume(
+                Curiosity::create(curious_about_everything()).unwrap(),
+                store,
+                unvisited,
+                progress,
+            );
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::Exhausted);
+            assert_eq!(graph_names(&ca).len(), 6);
+            assert!(ca
+                .progress()
+                .failures()
+                .all(|(iri, _)| !visited.iter().any(|v| v == iri)));
+        })
+    }
+
+    #[test]
+    fn store_ahead_of_snapshot() {
+        block_on(async {
+            let store = MemoryStore::default();
+            let mut ca = Agent::new(
+                Curiosity::create(curious_about_everything()).unwrap(),
+                store.clone(),
+                supergraph(),
+            )
+            .with_provenance_graph(named_node("urn:provenance"));
+            ca.investigate(named_node("did:a")).await.unwrap();
+            assert!(ca.next().await.unwrap());
+            let saved = serde_json::to_string(ca.progress()).unwrap();
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::Exhausted);
+            let finished = Store::len(&ca).unwrap();
+            drop(ca);
+
+            // The store kept being written to after the snapshot was taken, as if the crawl was
+            // killed before its progress could be saved again.
+            let mut ca = Agent::resume(
+                Curiosity::create(curious_about_everything()).unwrap(),
+                store.clone(),
+                supergraph(),
+                serde_json::from_str(&saved).unwrap(),
+            )
+            .with_provenance_graph(named_node("urn:provenance"));
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::Exhausted);
+            assert_eq!(Store::len(&ca).unwrap(), finished);
+        })
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn resume_on_disk() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let mut ca = Agent::new(
+                Curiosity::create(curious_about_everything()).unwrap(),
+                oxigraph::SledStore::open(dir.path()).unwrap(),
+                supergraph(),
+            )
+            .with_budget(CrawlBudget {
+                max_rounds: Some(2),
+                ..Default::default()
+            });
+            ca.investigate(named_node("did:a")).await.unwrap();
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::MaxRounds);
+            let saved = serde_json::to_string(ca.progress()).unwrap();
+            let visited = graph_names(&ca);
+            let len = Store::len(&ca).unwrap();
+            drop(ca);
+
+            let store = reopen(dir.path());
+            assert_eq!(graph_names(&store), visited);
+            assert_eq!(Store::len(&store).unwrap(), len);
+            let mut ca = Agent::resume(
+                Curiosity::create(curious_about_everything()).unwrap(),
+                store,
+                supergraph(),
+                serde_json::from_str(&saved).unwrap(),
+            );
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::Exhausted);
+            assert_eq!(graph_names(&ca).len(), 6);
+        })
+    }
+
+    #[test]
+    fn preconditions_keep_fresh_graphs() {
+        block_on(async {
+            let lookup: BTreeMap<&'static str, Graph> = [
+                ("did:a", "<did:a> <urn:link> <did:b> ."),
+                ("did:b", "<did:b> <urn:link> <did:a> ."),
+                ("urn:flag", "<urn:flag> <urn:ready> true ."),
+            ]
+            .iter()
+            .map(|(iri, ttl)| (*iri, from_ttl(ttl)))
+            .collect();
+            let mut ca = Agent::new(
+                Curiosity::create(vec![
+                    query("ASK { GRAPH <urn:flag> { ?s <urn:ready> true } }"),
+                    query("SELECT ?doc WHERE { GRAPH ?g { ?s <urn:link> ?doc } }"),
+                ])
+                .unwrap(),
+                MemoryStore::default(),
+                lookup,
+            );
+            ca.investigate(named_node("did:a")).await.unwrap();
+            // The precondition is false, so the link in did:a is not followed yet.
+            assert!(!ca.next().await.unwrap());
+            ca.investigate(named_node("urn:flag")).await.unwrap();
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::Exhausted);
+            assert!(ca.progress().visited(&named_node("did:b")));
+        })
+    }
+
+    /// A store whose writes fail while `broken` is set, and whose queries fail while `blind` is
+    /// set.
+    struct Fragile<'a> {
+        inner: MemoryStore,
+        broken: &'a AtomicBool,
+        blind: &'a AtomicBool,
+    }
+
+    impl Fragile<'_> {
+        fn check(&self) -> Result<(), EvaluationError> {
+            if self.broken.load(SeqCst) {
+                Err(std::io::Error::other("disk full").into())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl Store for Fragile<'_> {
+        type Error = EvaluationError;
+
+        fn insert(&mut self, quad: om::Quad) -> Result<(), EvaluationError> {
+            self.check()?;
+            self.inner.insert(quad);
+            Ok(())
+        }
+
+        fn remove(&mut self, quad: om::QuadRef<'_>) -> Result<(), EvaluationError> {
+            self.check()?;
+            self.inner.remove(quad);
+            Ok(())
+        }
+
+        fn query(&self, query: Query) -> Result<QueryResults, EvaluationError> {
+            if self.blind.load(SeqCst) {
+                return Err(std::io::Error::other("disk unreadable").into());
+            }
+            self.inner.query(query)
+        }
+
+        fn clear_graph(&mut self, graph: om::GraphNameRef<'_>) -> Result<(), EvaluationError> {
+            self.check()?;
+            self.inner.clear_graph(graph);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn storage_failures_are_distinct() {
+        block_on(async {
+            let broken = AtomicBool::new(true);
+            let store = Fragile {
+                inner: MemoryStore::default(),
+                broken: &broken,
+                blind: &AtomicBool::new(false),
+            };
+            let mut ca = Agent::new(
+                Curiosity::create(curious_about_everything()).unwrap(),
+                store,
+                supergraph(),
+            );
+            assert!(matches!(
+                ca.investigate(named_node("did:a")).await,
+                Err(AgentError::Store(_))
+            ));
+            assert!(matches!(
+                ca.investigate(named_node("did:missing")).await,
+                Err(AgentError::Lookup(LookupError::NotFound))
+            ));
+
+            // The document that could not be stored is not counted as a failed lookup; it is
+            // looked up again once the store works.
+            let failed: Vec<&str> = ca.progress().failures().map(|(iri, _)| iri).collect();
+            assert_eq!(failed, ["did:missing"]);
+            assert!(matches!(ca.crawl().await, Err(AgentError::Store(_))));
+            assert!(Store::is_empty(&ca).unwrap());
+            broken.store(false, SeqCst);
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::Exhausted);
+            assert_eq!(graph_names(&ca).len(), 6);
+        })
+    }
+
+    #[test]
+    fn provenance_check_failures_are_distinct() {
+        block_on(async {
+            let blind = AtomicBool::new(true);
+            let store = Fragile {
+                inner: MemoryStore::default(),
+                broken: &AtomicBool::new(false),
+                blind: &blind,
+            };
+            let mut ca = Agent::new(
+                Curiosity::create(curious_about_everything()).unwrap(),
+                store,
+                supergraph(),
+            )
+            .with_provenance_graph(named_node("urn:provenance"));
+            assert!(matches!(
+                ca.investigate(named_node("did:a")).await,
+                Err(AgentError::Store(_))
+            ));
+
+            // Nothing was written without knowing whether provenance was already recorded, and
+            // the document is looked up again once the store can be queried.
+            assert_eq!(ca.progress().failures().count(), 0);
+            blind.store(false, SeqCst);
+            assert!(Store::is_empty(&ca).unwrap());
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::Exhausted);
+            assert!(ca.progress().visited(&named_node("did:a")));
+            assert!(graph_names(&ca).contains(&"urn:provenance".to_string()));
+        })
+    }
+
+    /// Wraps a lookup, setting `blind` the first time `trigger` is looked up.
+    struct Blinding<'a, L> {
+        inner: L,
+        trigger: &'static str,
+        blind: &'a AtomicBool,
+        armed: AtomicBool,
+    }
+
+    #[async_trait::async_trait]
+    impl<L: Lookup + Sync> Lookup for Blinding<'_, L> {
+        async fn lookup(&self, iri: &om::NamedNode) -> Result<Graph, LookupError> {
+            let ret = self.inner.lookup(iri).await;
+            if iri.as_str() == self.trigger && self.armed.swap(false, SeqCst) {
+                self.blind.store(true, SeqCst);
+            }
+            ret
+        }
+    }
+
+    #[test]
+    fn provenance_check_failures_stop_crawl() {
+        block_on(async {
+            let blind = AtomicBool::new(false);
+            let store = Fragile {
+                inner: MemoryStore::default(),
+                broken: &AtomicBool::new(false),
+                blind: &blind,
+            };
+            let lookup = Throttled::new(Blinding {
+                inner: supergraph(),
+                trigger: "did:b",
+                blind: &blind,
+                armed: AtomicBool::new(true),
+            });
+            let mut ca = Agent::new(
+                Curiosity::create(curious_about_everything()).unwrap(),
+                store,
+                &lookup,
+            )
+            .with_concurrency(2)
+            .with_provenance_graph(named_node("urn:provenance"));
+            ca.investigate(named_node("did:a")).await.unwrap();
+            assert!(matches!(ca.crawl().await, Err(AgentError::Store(_))));
+            assert!(!ca.progress().visited(&named_node("did:b")));
+            assert!(ca.progress().frontier().any(|(iri, _)| iri == "did:b"));
+
+            // Crawling carries on once the store can be read again.
+            blind.store(false, SeqCst);
+            let mut complete = default_agent();
+            complete.investigate(named_node("did:a")).await.unwrap();
+            complete.crawl().await.unwrap();
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::Exhausted);
+            let mut expected = graph_names(&complete);
+            expected.push("urn:provenance".to_string());
+            expected.sort();
+            assert_eq!(graph_names(&ca), expected);
+        })
+    }
+
+    #[test]
+    fn crawl_into_sparql_endpoint() {
+        block_on(async {
+            let remote = MemoryStore::default();
+            let server = test_server::TestServer::sparql(remote.clone());
+            let mut ca = Agent::new(
+                Curiosity::create(curious_about_everything()).unwrap(),
+                SparqlStore::new(server.url("/query"), server.url("/update")),
+                supergraph(),
+            );
+            ca.investigate(named_node("did:a")).await.unwrap();
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::Exhausted);
+            assert_eq!(graph_names(&ca).len(), 6);
+
+            let local = MemoryStore::default();
+            let mut ca = Agent::new(
+                Curiosity::create(curious_about_everything()).unwrap(),
+                local.clone(),
+                supergraph(),
+            );
+            ca.investigate(named_node("did:a")).await.unwrap();
+            ca.crawl().await.unwrap();
+            assert!(remote.is_isomorphic(&local));
+        })
+    }
+
+    #[test]
+    fn incremental_sparql_default_graph() {
+        block_on(async {
+            let remote = MemoryStore::default();
+            remote.insert(om::Quad::new(
+                named_node("urn:config"),
+                named_node("urn:follow"),
+                named_node("urn:link"),
+                om::GraphName::DefaultGraph,
+            ));
+            let server = test_server::TestServer::sparql(remote);
+            let lookup: BTreeMap<&'static str, Graph> = [
+                ("did:a", "<did:a> <urn:link> <did:b> ."),
+                ("did:b", "<did:b> <urn:link> <did:c> ."),
+                ("did:c", "<did:c> <urn:other> <did:d> ."),
+            ]
+            .iter()
+            .map(|(iri, ttl)| (*iri, from_ttl(ttl)))
+            .collect();
+            let mut ca = Agent::new(
+                Curiosity::create(vec![query(
+                    "SELECT ?doc WHERE { <urn:config> <urn:follow> ?p . GRAPH ?g { ?s ?p ?doc } }",
+                )])
+                .unwrap(),
+                SparqlStore::new(server.url("/query"), server.url("/update")),
+                lookup,
+            );
+            ca.investigate(named_node("did:a")).await.unwrap();
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::Exhausted);
+            assert!(ca.progress().visited(&named_node("did:c")));
+            assert!(!ca.progress().visited(&named_node("did:d")));
+        })
+    }
+
+    /// Open a sled store that was just dropped. Sled releases its lock on the directory from a
+    /// background thread, so that may take a moment.
+    #[cfg(feature = "sled")]
+    fn reopen(path: &std::path::Path) -> oxigraph::SledStore {
+        for _ in 0..100 {
+            if let Ok(store) = oxigraph::SledStore::open(path) {
+                return store;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        oxigraph::SledStore::open(path).unwrap()
+    }
+
+    #[test]
+    fn most_interesting_first() {
+        block_on(async {
+            let attest = "https://dock.io/rdf/alpha/attestDocumentContent";
+            let one_document = CrawlBudget {
+                max_documents: Some(1),
+                ..Default::default()
+            };
+
+            let weighted = vec![
+                CuriousQuery::new(query("SELECT ?o WHERE { GRAPH ?g { ?s ?p ?o } }")),
+                CuriousQuery::new(query("SELECT ?p WHERE { GRAPH ?g { ?s ?p ?o } }")).weight(2.0),
+            ];
+            let mut ca = Agent::new(
+                Curiosity::create(weighted).unwrap(),
+                MemoryStore::default(),
+                supergraph(),
+            )
+            .with_budget(one_document.clone());
+            ca.investigate(named_node("did:a")).await.unwrap();
+            ca.crawl().await.unwrap();
+            assert!(ca.progress().failure(&named_node(attest)).is_some());
+            assert_eq!(graph_names(&ca), ["did:a"]);
+
+            let prioritized = vec![
+                query("SELECT ?o ?priority WHERE { GRAPH ?g { ?s ?p ?o } BIND(0 AS ?priority) }"),
+                query("SELECT ?p WHERE { GRAPH ?g { ?s ?p ?o } }"),
+            ];
+            let mut ca = Agent::new(
+                Curiosity::create(prioritized).unwrap(),
+                MemoryStore::default(),
+                supergraph(),
+            )
+            .with_budget(one_document);
+            ca.investigate(named_node("did:a")).await.unwrap();
+            ca.crawl().await.unwrap();
+            assert!(ca.progress().failure(&named_node(attest)).is_some());
+            assert_eq!(
+                ca.progress().frontier().collect::<Vec<_>>(),
+                [("did:a:claims", 0.0)]
+            );
+        })
+    }
+
+    #[test]
+    fn construct_and_ask() {
+        block_on(async {
+            let follow_attestations = format!(
+                "PREFIX dock: <https://dock.io/rdf/alpha/>
+                CONSTRUCT {{ ?doc <{}> true }}
+                WHERE {{ GRAPH ?g {{ ?s dock:attestDocumentContent ?doc }} }}",
+                SHOULD_FETCH
+            );
+            let follow_claimants = format!(
+                "PREFIX dock: <https://dock.io/rdf/alpha/>
+                CONSTRUCT {{ ?doc <{}> true }}
+                WHERE {{ GRAPH ?g {{ ?doc dock:mayclaim ?delegation }} }}",
+                SHOULD_FETCH
+            );
+            let mut ca = Agent::new(
+                Curiosity::create(vec![query(&follow_attestations), query(&follow_claimants)])
+                    .unwrap(),
+                MemoryStore::default(),
+                supergraph(),
+            );
+            ca.investigate(named_node("did:a")).await.unwrap();
+            ca.crawl().await.unwrap();
+            assert_eq!(graph_names(&ca).len(), 6);
+            assert_eq!(ca.progress().failures().count(), 0);
+
+            // Stop as soon as we learn who may make claims under the second delegation.
+            let until_delegate_known = "
+                PREFIX dock: <https://dock.io/rdf/alpha/>
+                ASK { FILTER NOT EXISTS { GRAPH ?g {
+                    ?who dock:mayclaim <uuid:ec3ae823-2e51-48ab-bdbf-bc41037eeead>
+                } } }
+            ";
+            let mut ca = Agent::new(
+                Curiosity::create(vec![
+                    query(&follow_attestations),
+                    query(&follow_claimants),
+                    query(until_delegate_known),
+                ])
+                .unwrap(),
+                MemoryStore::default(),
+                supergraph(),
+            );
+            ca.investigate(named_node("did:a")).await.unwrap();
+            ca.crawl().await.unwrap();
+            assert_eq!(
+                graph_names(&ca),
+                ["did:a", "did:a:claims", "did:b", "did:b:claims"]
+            );
+
+            assert_eq!(
+                Curiosity::create(vec![
+                    query("SELECT ?s WHERE { ?s ?p ?o }"),
+                    query("DESCRIBE <did:a>"),
+                ])
+                .err(),
+                Some(CuriosityError::Describe { index: 1 })
+            );
+        })
+    }
+
+    #[test]
+    fn only_document_variables() {
+        block_on(async {
+            let objects_by_convention = query("SELECT ?doc ?p WHERE { GRAPH ?g { ?s ?p ?doc } }");
+            let objects_by_name =
+                CuriousQuery::new(query("SELECT ?p ?o WHERE { GRAPH ?g { ?s ?p ?o } }"))
+                    .documents(vec!["o"]);
+            for curiosity in vec![
+                Curiosity::create(vec![objects_by_convention]),
+                Curiosity::create(vec![objects_by_name]),
+            ] {
+                let mut ca = Agent::new(curiosity.unwrap(), MemoryStore::default(), supergraph());
+                ca.investigate(named_node("did:a")).await.unwrap();
+                ca.crawl().await.unwrap();
+                assert_eq!(graph_names(&ca), ["did:a", "did:a:claims"]);
+                assert_eq!(
+                    ca.progress()
+                        .failures()
+                        .map(|(iri, _)| iri)
+                        .collect::<Vec<_>>(),
+                    ["uuid:d653df41-fb26-46b2-9edf-35a73836f7e0"]
+                );
+            }
+
+            assert_eq!(
+                Curiosity::create(vec![CuriousQuery::new(query(
+                    "SELECT ?s WHERE { GRAPH ?g { ?s ?p ?o } }"
+                ))
+                .documents(vec!["doc"])])
+                .err(),
+                Some(CuriosityError::UnknownVariable {
+                    index: 0,
+                    variable: "doc".into()
+                })
+            );
+        })
+    }
+
+    #[test]
+    fn provenance() {
+        block_on(async {
+            let attested = CuriousQuery::new(query(
+                "PREFIX dock: <https://dock.io/rdf/alpha/>
+                SELECT ?doc ?g WHERE { GRAPH ?g { ?s dock:attestDocumentContent ?doc } }",
+            ))
+            .name("attested");
+            let provenance_graph = named_node("urn:provenance");
+            let mut ca = Agent::new(
+                Curiosity::create(vec![attested]).unwrap(),
+                MemoryStore::default(),
+                supergraph(),
+            )
+            .with_provenance_graph(provenance_graph.clone());
+            ca.investigate(named_node("did:a")).await.unwrap();
+            ca.crawl().await.unwrap();
+
+            assert_eq!(ca.explain(&named_node("did:a")), None);
+            let mut solution = BTreeMap::new();
+            solution.insert("doc".to_string(), "<did:a:claims>".to_string());
+            solution.insert("g".to_string(), "<did:a>".to_string());
+            assert_eq!(
+                ca.explain(&named_node("did:a:claims")),
+                Some(&Provenance {
+                    query: 0,
+                    query_name: Some("attested".into()),
+                    graph: Some("<did:a>".into()),
+                    evidence: Evidence::Solution(solution),
+                })
+            );
+
+            let found_by = query(
+                "PREFIX dock: <https://dock.io/rdf/alpha/>
+                SELECT ?doc ?source WHERE { GRAPH <urn:provenance> {
+                    ?doc dock:foundBy [ dock:curiosityQueryName \"attested\" ; dock:sourceGraph ?source ]
+                } }",
+            );
+            let found: Vec<(String, String)> = match ca.query(found_by).unwrap() {
+                QueryResults::Solutions(solutions) => solutions
+                    .map(|s| {
+                        let s = s.unwrap();
+                        (
+                            s.get("doc").unwrap().to_string(),
+                            s.get("source").unwrap().to_string(),
+                        )
+                    })
+                    .collect(),
+                _ => panic!(),
+            };
+            assert_eq!(
+                found,
+                [("<did:a:claims>".to_string(), "<did:a>".to_string())]
+            );
+        })
+    }
+
+    #[test]
+    fn writes_behind_the_agents_back() {
+        block_on(async {
+            let lookup: BTreeMap<&'static str, Graph> = [
+                ("did:a", "<did:a> <urn:p> <urn:o> ."),
+                ("did:b", "<did:b> <urn:p> <urn:o> ."),
+            ]
+            .iter()
+            .map(|(iri, ttl)| (*iri, from_ttl(ttl)))
+            .collect();
+            let agent = |store: &MemoryStore| {
+                Agent::new(
+                    Curiosity::create(vec![query(
+                        "SELECT ?s WHERE { GRAPH ?g { ?s <urn:link> ?o } }",
+                    )])
+                    .unwrap(),
+                    store.clone(),
+                    lookup.clone(),
+                )
+            };
+            let link = |store: &MemoryStore, s: &str| {
+                store.insert(om::Quad::new(
+                    named_node(s),
+                    named_node("urn:link"),
+                    named_node("urn:o"),
+                    named_node("urn:g"),
+                ))
+            };
+
+            let store = MemoryStore::default();
+            let mut ca = agent(&store);
+            link(&store, "did:a");
+            ca.crawl().await.unwrap();
+            link(&store, "did:b");
+            ca.crawl().await.unwrap();
+            // The second link was not written by the agent, so it went unnoticed.
+            assert!(!ca.progress().visited(&named_node("did:b")));
+            ca.invalidate_curiosity();
+            ca.crawl().await.unwrap();
+            assert!(ca.progress().visited(&named_node("did:b")));
+
+            let store = MemoryStore::default();
+            let mut ca = agent(&store).with_incremental(false);
+            link(&store, "did:a");
+            ca.crawl().await.unwrap();
+            link(&store, "did:b");
+            ca.crawl().await.unwrap();
+            assert!(ca.progress().visited(&named_node("did:b")));
+        })
+    }
+
+    #[test]
+    fn budgeted_crawl() {
+        block_on(async {
+            let mut ca = default_agent().with_budget(CrawlBudget {
+                max_documents: Some(2),
+                ..Default::default()
+            });
+            ca.investigate(named_node("did:a")).await.unwrap();
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::MaxDocuments);
+            assert_eq!(graph_names(&ca), ["did:a", "did:a:claims"]);
+
+            let mut ca = default_agent().with_budget(CrawlBudget {
+                max_rounds: Some(1),
+                ..Default::default()
+            });
+            ca.investigate(named_node("did:a")).await.unwrap();
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::MaxRounds);
+            assert_eq!(graph_names(&ca), ["did:a", "did:a:claims"]);
+            while ca.crawl().await.unwrap() == CrawlOutcome::MaxRounds {}
+            assert_eq!(graph_names(&ca).len(), 6);
+
+            // Stepping through a crawl by hand stops when the budget runs out too.
+            let mut ca = default_agent().with_budget(CrawlBudget {
+                max_documents: Some(2),
+                ..Default::default()
+            });
+            ca.investigate(named_node("did:a")).await.unwrap();
+            while ca.next().await.unwrap() {}
+            assert_eq!(graph_names(&ca), ["did:a", "did:a:claims"]);
+        })
+    }
+
+    #[test]
+    fn stale_documents_are_replaced() {
+        block_on(async {
+            let documents = Mutex::new(BTreeMap::new());
+            let publish = |version: &str| {
+                let doc = from_ttl(&format!("<did:a> <urn:version> \"{}\" .", version));
+                documents.lock().unwrap().insert("did:a", doc);
+            };
+            fn versions(store: &impl Store) -> Vec<String> {
+                let q = query("SELECT ?v WHERE { GRAPH <did:a> { <did:a> <urn:version> ?v } }");
+                match store.query(q).unwrap() {
+                    QueryResults::Solutions(solutions) => solutions
+                        .map(|s| s.unwrap().get("v").unwrap().to_string())
+                        .collect(),
+                    _ => unreachable!(),
+                }
+            }
+            publish("1");
+            let mut ca = Agent::new(
+                Curiosity::create(curious_about_everything()).unwrap(),
+                MemoryStore::default(),
+                &documents,
+            );
+            ca.investigate(named_node("did:a")).await.unwrap();
+            let first_fetch = ca.progress().fetched_at(&named_node("did:a")).unwrap();
+            publish("2");
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::Exhausted);
+            assert_eq!(versions(&ca), ["\"1\""]);
+
+            let mut ca = ca
+                .with_max_age(Duration::from_secs(3600))
+                .with_budget(CrawlBudget {
+                    max_duration: Some(Duration::from_millis(10)),
+                    ..Default::default()
+                });
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::MaxDuration);
+            assert_eq!(versions(&ca), ["\"1\""]);
+
+            let mut ca = ca
+                .with_max_age(Duration::from_secs(0))
+                .with_budget(CrawlBudget {
+                    max_rounds: Some(2),
+                    ..Default::default()
+                });
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::MaxRounds);
+            assert_eq!(versions(&ca), ["\"2\""]);
+            assert!(ca.progress().fetched_at(&named_node("did:a")).unwrap() >= first_fetch);
+        })
+    }
+
+    #[test]
+    fn vanished_documents_are_cleared() {
+        block_on(async {
+            let documents = Mutex::new(BTreeMap::new());
+            let doc = from_ttl("<did:a> <urn:version> \"1\" .");
+            documents.lock().unwrap().insert("did:a", doc.clone());
+            let mut ca = Agent::new(
+                Curiosity::create(Vec::<Query>::new()).unwrap(),
+                MemoryStore::default(),
+                &documents,
+            )
+            .with_max_age(Duration::from_secs(0))
+            .with_budget(CrawlBudget {
+                max_rounds: Some(2),
+                ..Default::default()
+            });
+            ca.investigate(named_node("did:a")).await.unwrap();
+            assert_eq!(Store::len(&ca).unwrap(), 1);
+
+            // Refreshing finds the document gone, so its old contents are removed.
+            documents.lock().unwrap().remove("did:a");
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::MaxRounds);
+            assert!(Store::is_empty(&ca).unwrap());
+            let failed: Vec<&str> = ca.progress().failures().map(|(iri, _)| iri).collect();
+            assert_eq!(failed, ["did:a"]);
+
+            // It is still refreshed, so it comes back once it is published again.
+            documents.lock().unwrap().insert("did:a", doc);
+            assert_eq!(ca.crawl().await.unwrap(), CrawlOutcome::MaxRounds);
+            assert_eq!(Store::len(&ca).unwrap(), 1);
+            assert_eq!(ca.progress().failures().count(), 0);
+        })
+    }
+
+    #[async_trait::async_trait]
+    impl Lookup for &Mutex<BTreeMap<&'static str, Graph>> {
+        async fn lookup(&self, iri: &om::NamedNode) -> Result<Graph, LookupError> {
+            let documents = self.lock().unwrap().clone();
+            documents.lookup(iri).await
+        }
+    }
+
+    #[test]
+    fn concurrent_crawl() {
+        block_on(async {
+            let lookup = Throttled::new(supergraph());
+            let mut ca = Agent::new(
+                Curiosity::create(curious_about_everything()).unwrap(),
+                MemoryStore::default(),
+                &lookup,
+            )
+            .with_concurrency(2);
+            ca.investigate(named_node("did:a")).await.unwrap();
+            ca.crawl().await.unwrap();
+            let mut sequential = default_agent();
+            sequential.investigate(named_node("did:a")).await.unwrap();
+            sequential.crawl().await.unwrap();
+            assert_eq!(graph_names(&ca), graph_names(&sequential));
+            assert_eq!(lookup.max_in_flight.load(SeqCst), 2);
+        })
+    }
+
+    /// Wraps a lookup, yielding once per call so that concurrent lookups interleave.
+    struct Throttled<L> {
+        inner: L,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    impl<L> Throttled<L> {
+        fn new(inner: L) -> Self {
+            Self {
+                inner,
+                in_flight: AtomicUsize::new(0),
+                max_in_flight: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<L: Lookup + Sync> Lookup for &Throttled<L> {
+        async fn lookup(&self, iri: &om::NamedNode) -> Result<Graph, LookupError> {
+            let now = self.in_flight.fetch_add(1, SeqCst) + 1;
+            self.max_in_flight.fetch_max(now, SeqCst);
+            futures_lite::future::yield_now().await;
+            let ret = self.inner.lookup(iri).await;
+            self.in_flight.fetch_sub(1, SeqCst);
+            ret
+        }
+    }
+
     fn default_agent() -> Agent<MemoryStore, BTreeMap<&'static str, Graph>> {
         let curio = Curiosity::create(curious_about_everything()).unwrap();
         let memst = MemoryStore::default();
@@ -173,31 +1081,16 @@ mod test {
         .pipe(from_ttl)
     }
 
-    fn list_graphs(store: &impl Store) -> impl Iterator<Item = om::Term> {
-        fn as_solutions(q: QueryResults) -> Option<QuerySolutionIter> {
-            match q {
-                QueryResults::Solutions(solutions) => Some(solutions),
-                QueryResults::Boolean(_) | QueryResults::Graph(_) => None,
-            }
-        }
-
+    fn graph_names(store: &impl Store) -> Vec<String> {
         store
-            .query(
-                "SELECT DISTINCT ?g WHERE { GRAPH ?g { ?s ?p ?o } }"
-                    .parse()
-                    .unwrap(),
-            )
+            .named_graphs()
             .unwrap()
-            .pipe(as_solutions)
-            .unwrap()
-            .map(|a| a.unwrap())
-            .flat_map(|solution| {
-                solution
-                    .iter()
-                    .map(|(_k, v)| v)
-                    .cloned()
-                    .collect::<Vec<_>>()
+            .into_iter()
+            .map(|graph| match graph {
+                om::NamedOrBlankNode::NamedNode(nn) => nn.into_string(),
+                om::NamedOrBlankNode::BlankNode(bn) => panic!("unexpected graph {}", bn),
             })
+            .pipe(sorted)
     }
 
     fn sorted<T: Ord>(inp: impl IntoIterator<Item = T>) -> Vec<T> {
@@ -206,6 +1099,10 @@ mod test {
         ret
     }
 
+    fn query(q: &str) -> Query {
+        q.parse().unwrap()
+    }
+
     fn named_node(iri: &str) -> om::NamedNode {
         om::NamedNode::new(iri).unwrap()
     }
@@ -3,31 +3,130 @@
 use crate::rdf_graph::Graph;
 use alloc::collections::BTreeMap;
 use async_trait::async_trait;
-use core::fmt::{Debug, Display};
+use core::fmt;
 use oxigraph::model as om;
+use rio_api::parser::ParseError;
+use serde::{Deserialize, Serialize};
 use tap::prelude::*;
 
-// TODO: remove this stringly typed error
-#[derive(Debug)]
-pub struct LookupError(String);
+/// The reasons a lookup may fail.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LookupError {
+    /// There is no document at the requested iri.
+    NotFound,
+    /// The document could not be retrieved, e.g. the connection failed or the server returned an
+    /// error status.
+    Transport(String),
+    /// Retrieving the document took too long.
+    Timeout,
+    /// The document was retrieved but its media type is not one we know how to parse.
+    UnsupportedContentType(String),
+    /// The document was retrieved but could not be parsed. Line and column are one based and
+    /// present when the parser reports them.
+    Parse {
+        message: String,
+        line: Option<u64>,
+        column: Option<u64>,
+    },
+    /// The document is larger than the lookup is willing to retrieve.
+    TooLarge { limit: u64 },
+    /// The lookup declined to retrieve the document on principle, e.g. because the iri is not
+    /// one it is allowed to dereference.
+    Refused(String),
+    /// The document retrieved does not match the checksum in its iri.
+    Corrupt(String),
+    /// No lookup is configured for iris with this scheme.
+    NoBackend(String),
+}
+
+/// A fieldless summary of [`LookupError`], useful for categorising failures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum LookupErrorKind {
+    NotFound,
+    Transport,
+    Timeout,
+    UnsupportedContentType,
+    Parse,
+    TooLarge,
+    Refused,
+    Corrupt,
+    NoBackend,
+}
 
-impl<T: Display> From<T> for LookupError {
-    fn from(other: T) -> LookupError {
-        LookupError(format!("{}", other))
+impl LookupError {
+    pub fn kind(&self) -> LookupErrorKind {
+        match self {
+            LookupError::NotFound => LookupErrorKind::NotFound,
+            LookupError::Transport(_) => LookupErrorKind::Transport,
+            LookupError::Timeout => LookupErrorKind::Timeout,
+            LookupError::UnsupportedContentType(_) => LookupErrorKind::UnsupportedContentType,
+            LookupError::Parse { .. } => LookupErrorKind::Parse,
+            LookupError::TooLarge { .. } => LookupErrorKind::TooLarge,
+            LookupError::Refused(_) => LookupErrorKind::Refused,
+            LookupError::Corrupt(_) => LookupErrorKind::Corrupt,
+            LookupError::NoBackend(_) => LookupErrorKind::NoBackend,
+        }
+    }
+
+    /// Convert an error reported by one of the rio parsers, keeping its position.
+    pub fn from_parse_error(err: &impl ParseError) -> Self {
+        let position = err.textual_position();
+        LookupError::Parse {
+            message: err.to_string(),
+            line: position.map(|p| p.line_number()),
+            column: position.map(|p| p.byte_number()),
+        }
     }
 }
 
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LookupError::NotFound => write!(f, "document not found"),
+            LookupError::Transport(msg) => write!(f, "transport error: {}", msg),
+            LookupError::Timeout => write!(f, "timed out"),
+            LookupError::UnsupportedContentType(ct) => write!(f, "unsupported content-type {}", ct),
+            LookupError::Parse {
+                message,
+                line: Some(line),
+                column: Some(column),
+            } => write!(f, "parse error at {}:{}: {}", line, column, message),
+            LookupError::Parse { message, .. } => write!(f, "parse error: {}", message),
+            LookupError::TooLarge { limit } => {
+                write!(f, "document exceeds the size limit of {} bytes", limit)
+            }
+            LookupError::Refused(reason) => write!(f, "lookup refused: {}", reason),
+            LookupError::Corrupt(reason) => write!(f, "corrupt document: {}", reason),
+            LookupError::NoBackend(scheme) => write!(f, "no lookup for scheme {}", scheme),
+        }
+    }
+}
+
+impl std::error::Error for LookupError {}
+
+/// Lookups take `&self` so that an agent may have several of them in flight at once.
+/// Implementations that keep mutable state (e.g. a cache) should use interior mutability.
 #[async_trait]
 pub trait Lookup {
-    async fn lookup(&mut self, iri: &om::NamedNode) -> Result<Graph, LookupError>;
+    async fn lookup(&self, iri: &om::NamedNode) -> Result<Graph, LookupError>;
+}
+
+#[async_trait]
+impl<L: Lookup + Send + Sync + ?Sized> Lookup for Box<L> {
+    async fn lookup(&self, iri: &om::NamedNode) -> Result<Graph, LookupError> {
+        (**self).lookup(iri).await
+    }
 }
 
+// async_trait gives elided lifetimes in the implementing type names of their own, which clippy
+// then suggests eliding.
+#[allow(clippy::needless_lifetimes)]
 #[async_trait]
 impl Lookup for BTreeMap<&str, Graph> {
-    async fn lookup(&mut self, iri: &om::NamedNode) -> Result<Graph, LookupError> {
+    async fn lookup(&self, iri: &om::NamedNode) -> Result<Graph, LookupError> {
         self.get(iri.as_str())
-            .map(Clone::clone)
-            .ok_or("unregistered graph")?
+            .cloned()
+            .ok_or(LookupError::NotFound)?
             .pipe(Ok)
     }
 }
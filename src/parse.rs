@@ -0,0 +1,303 @@
+//! Turning serialized rdf documents into graphs.
+//!
+//! Every format rio can parse is supported. Quad formats (N-Quads and TriG) are flattened: a
+//! document is stored as a single named graph, so the triples of every graph in the document are
+//! merged and the graph names are dropped.
+
+use crate::convert::rm_to_om::triple;
+use crate::lookup::LookupError;
+use crate::rdf_graph::Graph;
+use oxigraph::model as om;
+use rio_api::model as rm;
+use rio_api::parser::{ParseError, QuadsParser, TriplesParser};
+use rio_turtle::{NQuadsParser, NTriplesParser, TriGParser, TurtleParser};
+use rio_xml::RdfXmlParser;
+use std::io::Cursor;
+use tap::prelude::*;
+
+/// Media types we know how to parse, most preferred first, along with the quality value to
+/// advertise for each in an `Accept` header.
+pub const MEDIA_TYPES: &[(&str, f32)] = &[
+    ("text/turtle", 1.0),
+    ("application/n-triples", 0.9),
+    ("application/rdf+xml", 0.8),
+    ("application/trig", 0.7),
+    ("application/n-quads", 0.7),
+    (JSON_LD, 0.6),
+];
+
+/// JSON-LD has to be read asynchronously, with [`Graph::from_json_ld`], because its contexts may
+/// need to be retrieved.
+pub const JSON_LD: &str = "application/ld+json";
+
+/// File extensions and the media types they imply.
+pub(crate) const EXTENSIONS: &[(&str, &str)] = &[
+    ("ttl", "text/turtle"),
+    ("nt", "application/n-triples"),
+    ("rdf", "application/rdf+xml"),
+    ("owl", "application/rdf+xml"),
+    ("trig", "application/trig"),
+    ("nq", "application/n-quads"),
+    ("jsonld", JSON_LD),
+];
+
+/// Parse a document retrieved from `location`.
+///
+/// `content_type` may include parameters, e.g. `text/turtle; charset=utf-8`. When it is missing
+/// or too generic to be useful (e.g. `application/octet-stream`) the format is guessed from the
+/// file extension of `location`. Relative iris are resolved against `location`.
+pub fn parse_document(
+    body: &[u8],
+    content_type: Option<&str>,
+    location: Option<&str>,
+) -> Result<Graph, LookupError> {
+    parse(
+        body,
+        &document_media_type(content_type, location)?,
+        location,
+    )
+}
+
+/// The media type of a document, as decided by [`parse_document`].
+pub fn document_media_type(
+    content_type: Option<&str>,
+    location: Option<&str>,
+) -> Result<String, LookupError> {
+    let media_type = content_type.map(rdf_media_type).transpose()?;
+    match media_type.as_deref() {
+        None | Some("application/octet-stream") | Some("text/plain") => location
+            .and_then(media_type_for_extension)
+            .map(str::to_string)
+            .or(media_type)
+            .ok_or_else(|| LookupError::UnsupportedContentType("none".into())),
+        Some("application/json") | Some("application/did+ld+json") => Ok(JSON_LD.into()),
+        Some(_) => Ok(media_type.unwrap()),
+    }
+}
+
+/// Parse `body` as a document of the given media type. `media_type` is the essence of a
+/// content-type, without parameters. Relative iris are resolved against `base`.
+pub fn parse(body: &[u8], media_type: &str, base: Option<&str>) -> Result<Graph, LookupError> {
+    let base = || {
+        base.map(|iri| {
+            oxiri::Iri::parse(iri.to_string()).map_err(|e| LookupError::Parse {
+                message: format!("invalid base iri {}: {}", iri, e),
+                line: None,
+                column: None,
+            })
+        })
+        .transpose()
+    };
+    let body = Cursor::new(body);
+    match media_type {
+        "text/turtle" | "application/x-turtle" => triples(TurtleParser::new(body, base()?)),
+        "application/n-triples" => triples(NTriplesParser::new(body)),
+        "application/rdf+xml" => triples(RdfXmlParser::new(body, base()?)),
+        "application/trig" => quads(TriGParser::new(body, base()?)),
+        "application/n-quads" => quads(NQuadsParser::new(body)),
+        _ => Err(LookupError::UnsupportedContentType(media_type.to_string())),
+    }
+}
+
+/// The media type implied by the file extension at the end of a path or iri, if we can parse it.
+pub fn media_type_for_extension(path: &str) -> Option<&'static str> {
+    let path = path.split(['?', '#']).next()?;
+    let file_name = path.rsplit('/').next()?;
+    let (_, extension) = file_name.rsplit_once('.')?;
+    EXTENSIONS
+        .iter()
+        .find(|(ext, _)| ext.eq_ignore_ascii_case(extension))
+        .map(|(_, media_type)| *media_type)
+}
+
+/// The value of an `Accept` header listing every media type in [`MEDIA_TYPES`].
+pub fn accept() -> String {
+    MEDIA_TYPES
+        .iter()
+        .map(|(mt, q)| {
+            if *q < 1.0 {
+                format!("{};q={}", mt, q)
+            } else {
+                mt.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The lowercased essence of a content-type header, e.g. `text/turtle` for
+/// `Text/Turtle; charset="UTF-8"`. All the formats we parse are utf-8 so any other charset is
+/// rejected.
+pub fn rdf_media_type(content_type: &str) -> Result<String, LookupError> {
+    let unsupported = || LookupError::UnsupportedContentType(content_type.to_string());
+    let mut parts = content_type.split(';');
+    let essence = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+    for param in parts {
+        let mut kv = param.splitn(2, '=');
+        let key = kv.next().unwrap_or_default().trim();
+        let value = kv.next().unwrap_or_default().trim().trim_matches('"');
+        if key.eq_ignore_ascii_case("charset")
+            && !(value.eq_ignore_ascii_case("utf-8") || value.eq_ignore_ascii_case("us-ascii"))
+        {
+            return Err(unsupported());
+        }
+    }
+    if essence.is_empty() {
+        return Err(unsupported());
+    }
+    Ok(essence)
+}
+
+fn triples<P>(p: P) -> Result<Graph, LookupError>
+where
+    P: TriplesParser,
+    P::Error: ParseError,
+{
+    p.into_iter(|t| Ok::<_, P::Error>(triple(t)))
+        .map(convert_result::<P::Error>)
+        .collect::<Result<Vec<om::Triple>, LookupError>>()?
+        .into_iter()
+        .pipe(Graph::new)
+        .pipe(Ok)
+}
+
+fn quads<P>(p: P) -> Result<Graph, LookupError>
+where
+    P: QuadsParser,
+    P::Error: ParseError,
+{
+    p.into_iter(|q| {
+        let rm::Quad {
+            subject,
+            predicate,
+            object,
+            graph_name: _,
+        } = q;
+        Ok::<_, P::Error>(triple(rm::Triple {
+            subject,
+            predicate,
+            object,
+        }))
+    })
+    .map(convert_result::<P::Error>)
+    .collect::<Result<Vec<om::Triple>, LookupError>>()?
+    .into_iter()
+    .pipe(Graph::new)
+    .pipe(Ok)
+}
+
+fn convert_result<E: ParseError>(
+    t: Result<Result<om::Triple, crate::convert::rm_to_om::ConvertErr>, E>,
+) -> Result<om::Triple, LookupError> {
+    match t {
+        Ok(Ok(t)) => Ok(t),
+        Ok(Err(e)) => Err(LookupError::Parse {
+            message: e.to_string(),
+            line: None,
+            column: None,
+        }),
+        Err(e) => Err(LookupError::from_parse_error(&e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ttl::from_ttl;
+
+    #[test]
+    fn formats() {
+        let expected = from_ttl(
+            "<http://example.com/a> <http://schema.org/knows> <http://example.com/b> .
+            <http://example.com/b> <http://schema.org/name> \"b\"@en .",
+        );
+        let documents = [
+            (
+                "text/turtle",
+                "@prefix s: <http://schema.org/> .
+                <a> s:knows <b> . <b> s:name \"b\"@en .",
+            ),
+            (
+                "application/n-triples",
+                "<http://example.com/a> <http://schema.org/knows> <http://example.com/b> .
+                <http://example.com/b> <http://schema.org/name> \"b\"@en .",
+            ),
+            (
+                "application/rdf+xml",
+                r#"<?xml version="1.0"?>
+                <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+                         xmlns:s="http://schema.org/">
+                  <rdf:Description rdf:about="a"><s:knows rdf:resource="b"/></rdf:Description>
+                  <rdf:Description rdf:about="b"><s:name xml:lang="en">b</s:name></rdf:Description>
+                </rdf:RDF>"#,
+            ),
+            (
+                "application/trig",
+                "@prefix s: <http://schema.org/> .
+                <a> s:knows <b> . <g> { <b> s:name \"b\"@en . }",
+            ),
+            (
+                "application/n-quads",
+                "<http://example.com/a> <http://schema.org/knows> <http://example.com/b> .
+                <http://example.com/b> <http://schema.org/name> \"b\"@en <http://example.com/g> .",
+            ),
+        ];
+        for (media_type, body) in documents.iter() {
+            assert_eq!(
+                parse(body.as_bytes(), media_type, Some("http://example.com/doc")),
+                Ok(expected.clone()),
+                "{}",
+                media_type
+            );
+        }
+    }
+
+    #[test]
+    fn falls_back_to_extension() {
+        let body = b"<http://example.com/a> <http://schema.org/name> \"a\" .";
+        let expected = Ok(from_ttl(
+            "<http://example.com/a> <http://schema.org/name> \"a\" .",
+        ));
+        assert_eq!(
+            parse_document(body, None, Some("http://example.com/doc.nt")),
+            expected
+        );
+        assert_eq!(
+            parse_document(
+                body,
+                Some("application/octet-stream"),
+                Some("/data/doc.NT?version=2")
+            ),
+            expected
+        );
+        assert_eq!(
+            parse_document(body, Some("application/n-triples"), Some("doc.ttl")),
+            expected
+        );
+        assert_eq!(
+            parse_document(body, None, Some("doc")),
+            Err(LookupError::UnsupportedContentType("none".into()))
+        );
+        assert_eq!(
+            parse_document(body, Some("text/plain"), None),
+            Err(LookupError::UnsupportedContentType("text/plain".into()))
+        );
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        let err = parse(
+            b"<urn:a> <urn:b> <urn:c> .\n<urn:a> <urn:b> .",
+            "application/n-triples",
+            None,
+        );
+        assert!(matches!(
+            err,
+            Err(LookupError::Parse {
+                line: Some(2),
+                column: Some(17),
+                ..
+            })
+        ));
+    }
+}
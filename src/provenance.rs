@@ -0,0 +1,163 @@
+//! Records of why the agent decided to look up a document.
+
+use crate::store::Store;
+use alloc::collections::BTreeMap;
+use oxigraph::io::{GraphFormat, GraphParser};
+use oxigraph::model as om;
+use oxigraph::sparql::{algebra::Query, QueryResults};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+/// The curiosity query result that first made the agent curious about a document.
+///
+/// Terms are kept in N-Triples syntax so that provenance can be saved along with the rest of the
+/// crawl progress.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Position of the query in the list passed to [`Curiosity::create`](crate::Curiosity::create).
+    pub query: usize,
+    pub query_name: Option<String>,
+    /// The named graph the query matched in, if it is named in the query or bound to a selected
+    /// variable.
+    pub graph: Option<String>,
+    pub evidence: Evidence,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Evidence {
+    /// A solution to a SELECT query, as a map from variable name to term.
+    Solution(BTreeMap<String, String>),
+    /// A triple produced by a CONSTRUCT query.
+    Constructed {
+        subject: String,
+        predicate: String,
+        object: String,
+    },
+}
+
+const DOCK: &str = "https://dock.io/rdf/alpha/";
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const XSD_INTEGER: &str = "http://www.w3.org/2001/XMLSchema#integer";
+
+impl Provenance {
+    /// Describe this provenance in rdf, as quads in `graph`:
+    ///
+    /// ```turtle
+    /// <document> dock:foundBy [
+    ///     dock:curiosityQuery 0 ;
+    ///     dock:curiosityQueryName "name" ;
+    ///     dock:sourceGraph <graph> ;
+    ///     dock:binding [ dock:variable "s" ; dock:value <did:a> ] ;
+    /// ] .
+    /// ```
+    ///
+    /// A triple constructed by a CONSTRUCT query is reified as the object of `dock:constructed`.
+    pub fn to_quads(&self, document: &om::NamedNode, graph: &om::NamedNode) -> Vec<om::Quad> {
+        let mut ret = Vec::new();
+        let mut add = |s: om::NamedOrBlankNode, p: om::NamedNode, o: om::Term| {
+            ret.push(om::Quad::new(s, p, o, graph.clone()));
+        };
+        let cause = om::BlankNode::default();
+        add(
+            document.clone().into(),
+            dock("foundBy"),
+            cause.clone().into(),
+        );
+        add(
+            cause.clone().into(),
+            dock("curiosityQuery"),
+            om::Literal::new_typed_literal(
+                self.query.to_string(),
+                om::NamedNode::new_unchecked(XSD_INTEGER),
+            )
+            .into(),
+        );
+        if let Some(name) = &self.query_name {
+            add(
+                cause.clone().into(),
+                dock("curiosityQueryName"),
+                om::Literal::new_simple_literal(name).into(),
+            );
+        }
+        if let Some(source) = self.graph.as_deref().and_then(parse_term) {
+            add(cause.clone().into(), dock("sourceGraph"), source);
+        }
+        match &self.evidence {
+            Evidence::Solution(solution) => {
+                for (variable, value) in solution {
+                    let value = match parse_term(value) {
+                        Some(value) => value,
+                        None => continue,
+                    };
+                    let binding = om::BlankNode::default();
+                    add(
+                        cause.clone().into(),
+                        dock("binding"),
+                        binding.clone().into(),
+                    );
+                    add(
+                        binding.clone().into(),
+                        dock("variable"),
+                        om::Literal::new_simple_literal(variable).into(),
+                    );
+                    add(binding.into(), dock("value"), value);
+                }
+            }
+            Evidence::Constructed {
+                subject,
+                predicate,
+                object,
+            } => {
+                let statement = om::BlankNode::default();
+                add(cause.into(), dock("constructed"), statement.clone().into());
+                for (p, o) in [
+                    ("subject", subject),
+                    ("predicate", predicate),
+                    ("object", object),
+                ]
+                .iter()
+                {
+                    if let Some(o) = parse_term(o) {
+                        add(statement.clone().into(), rdf(p), o);
+                    }
+                }
+            }
+        }
+        ret
+    }
+}
+
+/// Whether the provenance of `document` has already been written to `graph` of `store`.
+pub(crate) fn is_recorded<S: Store>(
+    store: &S,
+    document: &om::NamedNode,
+    graph: &om::NamedNode,
+) -> Result<bool, S::Error> {
+    let query = format!(
+        "ASK {{ GRAPH {} {{ {} {} ?cause }} }}",
+        graph,
+        document,
+        dock("foundBy")
+    );
+    let query = Query::parse(&query, None).expect("provenance query is valid");
+    Ok(matches!(store.query(query)?, QueryResults::Boolean(true)))
+}
+
+fn dock(suffix: &str) -> om::NamedNode {
+    om::NamedNode::new_unchecked(format!("{}{}", DOCK, suffix))
+}
+
+fn rdf(suffix: &str) -> om::NamedNode {
+    om::NamedNode::new_unchecked(format!("{}{}", RDF, suffix))
+}
+
+/// Parse a single term written in N-Triples syntax.
+fn parse_term(nt: &str) -> Option<om::Term> {
+    let doc = format!("<urn:s> <urn:p> {} .", nt);
+    GraphParser::from_format(GraphFormat::NTriples)
+        .read_triples(Cursor::new(doc))
+        .ok()?
+        .next()?
+        .ok()
+        .map(|t| t.object)
+}
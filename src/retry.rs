@@ -0,0 +1,104 @@
+//! Deciding when a failed lookup deserves another attempt.
+
+use crate::crawl_progress::Failure;
+use crate::lookup::LookupErrorKind;
+use alloc::collections::BTreeSet;
+use std::time::{Duration, SystemTime};
+
+/// Failed lookups whose error kind is in `retryable` are attempted again after an exponentially
+/// growing delay, up to `max_attempts` attempts in total.
+///
+/// The default policy makes three attempts at documents which failed because of transport errors
+/// or timeouts, waiting one second and then two seconds between attempts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first. A value of 1 disables retries.
+    pub max_attempts: u32,
+    /// Delay before the second attempt.
+    pub initial_backoff: Duration,
+    /// Each subsequent delay is this many times longer than the last.
+    pub multiplier: u32,
+    /// Delays never grow past this.
+    pub max_backoff: Duration,
+    pub retryable: BTreeSet<LookupErrorKind>,
+}
+
+impl RetryPolicy {
+    /// Never retry; a failed document stays failed.
+    pub fn never() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// When the failed document may next be attempted, or None if it should not be retried. A
+    /// backoff too long to be represented, e.g. `Duration::MAX`, means never.
+    pub fn retry_at(&self, failure: &Failure) -> Option<SystemTime> {
+        if failure.attempts >= self.max_attempts || !self.retryable.contains(&failure.error.kind())
+        {
+            return None;
+        }
+        failure
+            .last_failed
+            .checked_add(self.backoff(failure.attempts))
+    }
+
+    /// The delay after the `attempts`th failed attempt.
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .checked_pow(attempts.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial_backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+            multiplier: 2,
+            max_backoff: Duration::from_secs(60),
+            retryable: [LookupErrorKind::Transport, LookupErrorKind::Timeout]
+                .iter()
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lookup::LookupError;
+
+    #[test]
+    fn unrepresentable_backoff_is_never() {
+        let now = SystemTime::now();
+        let failure = Failure {
+            error: LookupError::Timeout,
+            attempts: 1,
+            first_failed: now,
+            last_failed: now,
+        };
+        let policy = RetryPolicy {
+            max_backoff: Duration::MAX,
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.retry_at(&failure),
+            Some(now + Duration::from_secs(1))
+        );
+        let policy = RetryPolicy {
+            initial_backoff: Duration::MAX,
+            max_backoff: Duration::MAX,
+            ..Default::default()
+        };
+        assert_eq!(policy.retry_at(&failure), None);
+    }
+}
@@ -0,0 +1,254 @@
+//! Combining lookups so that a single agent can crawl documents from several sources.
+
+use crate::lookup::{Lookup, LookupError, LookupErrorKind};
+use crate::rdf_graph::Graph;
+use async_trait::async_trait;
+use oxigraph::model as om;
+
+type Backend = Box<dyn Lookup + Send + Sync>;
+
+/// Dispatches each iri to the lookups configured for its prefix or, failing that, its scheme.
+/// When several prefixes match, the longest wins.
+///
+/// A route may have several lookups. They are tried in the order they were added until one
+/// succeeds, e.g. an [`IpfsLookup`](crate::IpfsLookup) followed by a gateway reached through
+/// [`Rewrite`]. When all of them fail, the most telling of their errors is returned: one showing
+/// that the document exists but is unusable, e.g. [`LookupError::Corrupt`], wins over one showing
+/// that a lookup could not reach it, which wins over [`LookupError::NotFound`].
+///
+/// ```
+/// # use quaerit_machina::{HttpLookup, IpfsLookup, RouterLookup, Rewrite};
+/// let lookup = RouterLookup::new()
+///     .with_scheme("https", HttpLookup::new())
+///     .with_scheme("ipfs", IpfsLookup::new())
+///     .with_scheme(
+///         "ipfs",
+///         Rewrite::new(HttpLookup::new(), |iri| {
+///             let cid = iri.strip_prefix("ipfs://")?;
+///             Some(format!("https://ipfs.io/ipfs/{}", cid.trim_start_matches("ipfs/")))
+///         }),
+///     );
+/// ```
+#[derive(Default)]
+pub struct RouterLookup {
+    prefixes: Vec<(String, Vec<Backend>)>,
+    schemes: Vec<(String, Vec<Backend>)>,
+}
+
+impl RouterLookup {
+    /// A router with no routes, which has no backend for any iri.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Look up iris with this scheme, e.g. `"https"`, using `lookup`. Schemes are compared case
+    /// insensitively.
+    pub fn with_scheme(
+        mut self,
+        scheme: &str,
+        lookup: impl Lookup + Send + Sync + 'static,
+    ) -> Self {
+        add(
+            &mut self.schemes,
+            scheme.to_ascii_lowercase(),
+            Box::new(lookup),
+        );
+        self
+    }
+
+    /// Look up iris starting with `prefix` using `lookup`.
+    pub fn with_prefix(
+        mut self,
+        prefix: impl Into<String>,
+        lookup: impl Lookup + Send + Sync + 'static,
+    ) -> Self {
+        add(&mut self.prefixes, prefix.into(), Box::new(lookup));
+        self.prefixes
+            .sort_by_key(|(prefix, _)| core::cmp::Reverse(prefix.len()));
+        self
+    }
+
+    fn backends(&self, iri: &str) -> Result<&[Backend], LookupError> {
+        if let Some((_, backends)) = self
+            .prefixes
+            .iter()
+            .find(|(prefix, _)| iri.starts_with(prefix.as_str()))
+        {
+            return Ok(backends);
+        }
+        let scheme = iri
+            .split(':')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        self.schemes
+            .iter()
+            .find(|(s, _)| *s == scheme)
+            .map(|(_, backends)| backends.as_slice())
+            .ok_or(LookupError::NoBackend(scheme))
+    }
+}
+
+fn add(routes: &mut Vec<(String, Vec<Backend>)>, key: String, backend: Backend) {
+    match routes.iter_mut().find(|(k, _)| *k == key) {
+        Some((_, backends)) => backends.push(backend),
+        None => routes.push((key, vec![backend])),
+    }
+}
+
+#[async_trait]
+impl Lookup for RouterLookup {
+    async fn lookup(&self, iri: &om::NamedNode) -> Result<Graph, LookupError> {
+        let (last, rest) = self
+            .backends(iri.as_str())?
+            .split_last()
+            .expect("every route has a lookup");
+        let mut failure: Option<LookupError> = None;
+        for backend in rest {
+            match backend.lookup(iri).await {
+                Ok(graph) => return Ok(graph),
+                Err(e) => failure = Some(most_telling(failure, e)),
+            }
+        }
+        last.lookup(iri).await.map_err(|e| most_telling(failure, e))
+    }
+}
+
+/// The more telling of two errors, `later` when they are equally so.
+fn most_telling(earlier: Option<LookupError>, later: LookupError) -> LookupError {
+    fn rank(e: &LookupError) -> u8 {
+        match e.kind() {
+            LookupErrorKind::NoBackend | LookupErrorKind::Refused => 0,
+            LookupErrorKind::NotFound => 1,
+            LookupErrorKind::Transport | LookupErrorKind::Timeout => 2,
+            LookupErrorKind::UnsupportedContentType | LookupErrorKind::Parse => 3,
+            LookupErrorKind::TooLarge | LookupErrorKind::Corrupt => 4,
+        }
+    }
+    match earlier {
+        Some(earlier) if rank(&earlier) > rank(&later) => earlier,
+        _ => later,
+    }
+}
+
+/// Looks up a different iri in place of the one asked for, e.g. to reach ipfs documents through
+/// an HTTP gateway. Relative iris in the document are resolved however `L` resolves them, which
+/// is usually against the rewritten iri.
+pub struct Rewrite<L, F> {
+    lookup: L,
+    rewrite: F,
+}
+
+impl<L, F> Rewrite<L, F>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    /// Look up `rewrite(iri)` with `lookup`. Iris which `rewrite` maps to `None` are refused.
+    pub fn new(lookup: L, rewrite: F) -> Self {
+        Self { lookup, rewrite }
+    }
+}
+
+#[async_trait]
+impl<L, F> Lookup for Rewrite<L, F>
+where
+    L: Lookup + Send + Sync,
+    F: Fn(&str) -> Option<String> + Send + Sync,
+{
+    async fn lookup(&self, iri: &om::NamedNode) -> Result<Graph, LookupError> {
+        let rewritten = (self.rewrite)(iri.as_str())
+            .ok_or_else(|| LookupError::Refused(format!("{} can not be rewritten", iri)))?;
+        let rewritten = om::NamedNode::new(rewritten).map_err(|e| {
+            LookupError::Refused(format!("{} was rewritten to an invalid iri: {}", iri, e))
+        })?;
+        self.lookup.lookup(&rewritten).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ttl::from_ttl;
+    use alloc::collections::BTreeMap;
+    use futures::executor::block_on;
+
+    #[test]
+    fn routes() {
+        let doc = |name: &str| from_ttl(&format!("<urn:doc> <urn:from> \"{}\" .", name));
+        let backend = |name: &str, iris: &[&'static str]| {
+            iris.iter()
+                .map(|iri| (*iri, doc(name)))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let router = RouterLookup::new()
+            .with_scheme("HTTPS", backend("https", &["https://a.example/doc"]))
+            .with_prefix(
+                "https://b.example/",
+                backend("b", &["https://b.example/doc"]),
+            )
+            .with_prefix(
+                "https://b.example/private/",
+                backend("private", &["https://b.example/private/doc"]),
+            )
+            .with_scheme("ipfs", backend("ipfs", &["ipfs://a"]))
+            .with_scheme(
+                "ipfs",
+                Rewrite::new(
+                    backend(
+                        "gateway",
+                        &[
+                            "https://gateway.example/ipfs/a",
+                            "https://gateway.example/ipfs/b",
+                        ],
+                    ),
+                    |iri| {
+                        Some(format!(
+                            "https://gateway.example/ipfs/{}",
+                            iri.strip_prefix("ipfs://")?
+                        ))
+                    },
+                ),
+            );
+        let lookup = |iri: &str| block_on(router.lookup(&om::NamedNode::new(iri).unwrap()));
+
+        assert_eq!(lookup("https://a.example/doc"), Ok(doc("https")));
+        assert_eq!(lookup("Https://a.example/doc"), Err(LookupError::NotFound));
+        assert_eq!(lookup("https://b.example/doc"), Ok(doc("b")));
+        assert_eq!(lookup("https://b.example/private/doc"), Ok(doc("private")));
+        assert_eq!(
+            lookup("https://b.example/other"),
+            Err(LookupError::NotFound)
+        );
+        assert_eq!(lookup("ipfs://a"), Ok(doc("ipfs")));
+        assert_eq!(lookup("ipfs://b"), Ok(doc("gateway")));
+        assert_eq!(lookup("ipfs://c"), Err(LookupError::NotFound));
+        assert_eq!(
+            lookup("did:example:a"),
+            Err(LookupError::NoBackend("did".into()))
+        );
+    }
+
+    #[test]
+    fn keeps_most_telling_error() {
+        let corrupt = LookupError::Corrupt("block does not hash to a".into());
+        let timeout = LookupError::Timeout;
+        let router = RouterLookup::new()
+            .with_scheme("ipfs", Failing(corrupt.clone()))
+            .with_scheme("ipfs", Failing(LookupError::NotFound))
+            .with_scheme("https", Failing(LookupError::Refused("private".into())))
+            .with_scheme("https", Failing(timeout.clone()))
+            .with_scheme("https", Failing(LookupError::NotFound));
+        let lookup = |iri: &str| block_on(router.lookup(&om::NamedNode::new(iri).unwrap()));
+        assert_eq!(lookup("ipfs://a"), Err(corrupt));
+        assert_eq!(lookup("https://a.example/doc"), Err(timeout));
+    }
+
+    struct Failing(LookupError);
+
+    #[async_trait]
+    impl Lookup for Failing {
+        async fn lookup(&self, _: &om::NamedNode) -> Result<Graph, LookupError> {
+            Err(self.0.clone())
+        }
+    }
+}
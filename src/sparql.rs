@@ -0,0 +1,307 @@
+//! Keeping the knowledge graph in a remote triplestore.
+
+use crate::store::Store;
+use core::fmt;
+use oxigraph::io::GraphFormat;
+use oxigraph::model as om;
+use oxigraph::sparql::{algebra::Query, EvaluationError, QueryResults, QueryResultsFormat};
+use oxigraph::MemoryStore;
+use std::io::{self, BufReader};
+use std::time::Duration;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A [`Store`] kept in a triplestore that speaks the SPARQL 1.1 protocol, such as oxigraph's
+/// server. Queries are sent to one endpoint and updates to another, which may be the same.
+///
+/// Requests block the calling thread, so unlike [`HttpLookup`](crate::HttpLookup) this works
+/// with or without an async runtime.
+///
+/// Each [`commit`](Store::commit) is sent as a single update request, which SPARQL 1.1 services
+/// should apply atomically. Blank nodes do not survive the trip to the endpoint: those inserted
+/// by separate requests are always distinct, quads containing them can not be removed, and
+/// [`contains`](Store::contains) treats them as variables.
+///
+/// SPARQL 1.1 services read an empty default graph when a query lists only named graphs, so
+/// curiosity queries that read the default graph are evaluated against the whole store each round.
+pub struct SparqlStore {
+    agent: ureq::Agent,
+    query_endpoint: String,
+    update_endpoint: String,
+    timeout: Duration,
+}
+
+/// The reasons a request to a SPARQL endpoint may fail.
+#[derive(Debug)]
+pub enum SparqlStoreError {
+    /// The endpoint could not be reached or the connection failed.
+    Transport(String),
+    /// The endpoint responded with an error status.
+    Status { status: u16, message: String },
+    /// The endpoint's response could not be understood.
+    Response(String),
+    /// The request would need to refer to a blank node.
+    BlankNode,
+}
+
+impl fmt::Display for SparqlStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SparqlStoreError::Transport(msg) => write!(f, "transport error: {}", msg),
+            SparqlStoreError::Status { status, message } => {
+                write!(f, "endpoint returned status {}: {}", status, message)
+            }
+            SparqlStoreError::Response(msg) => write!(f, "unexpected response: {}", msg),
+            SparqlStoreError::BlankNode => write!(f, "blank nodes can not be sent to the endpoint"),
+        }
+    }
+}
+
+impl std::error::Error for SparqlStoreError {}
+
+impl From<EvaluationError> for SparqlStoreError {
+    fn from(e: EvaluationError) -> Self {
+        // Errors from the endpoint come back from `Store::query` wrapped in an io error.
+        match e {
+            EvaluationError::Io(e) if e.get_ref().is_some_and(|e| e.is::<SparqlStoreError>()) => *e
+                .into_inner()
+                .expect("checked above")
+                .downcast()
+                .expect("checked above"),
+            e => SparqlStoreError::Response(e.to_string()),
+        }
+    }
+}
+
+impl SparqlStore {
+    pub fn new(query_endpoint: impl Into<String>, update_endpoint: impl Into<String>) -> Self {
+        Self {
+            agent: ureq::Agent::new(),
+            query_endpoint: query_endpoint.into(),
+            update_endpoint: update_endpoint.into(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Give up on a request if it has not completed within `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn post(
+        &self,
+        endpoint: &str,
+        content_type: &str,
+        accept: &str,
+        body: &str,
+    ) -> Result<ureq::Response, SparqlStoreError> {
+        let response = self
+            .agent
+            .post(endpoint)
+            .timeout(self.timeout)
+            .set("Content-Type", content_type)
+            .set("Accept", accept)
+            .send_string(body);
+        match response {
+            Ok(response) => Ok(response),
+            Err(ureq::Error::Status(status, response)) => Err(SparqlStoreError::Status {
+                status,
+                message: response.into_string().unwrap_or_default(),
+            }),
+            Err(ureq::Error::Transport(e)) => Err(SparqlStoreError::Transport(e.to_string())),
+        }
+    }
+
+    fn update(&self, update: &str) -> Result<(), SparqlStoreError> {
+        self.post(
+            &self.update_endpoint,
+            "application/sparql-update",
+            "*/*",
+            update,
+        )?;
+        Ok(())
+    }
+
+    fn evaluate(&self, query: &Query) -> Result<QueryResults, SparqlStoreError> {
+        let graph = matches!(query, Query::Construct { .. } | Query::Describe { .. });
+        let accept = if graph {
+            GraphFormat::NTriples.media_type()
+        } else {
+            QueryResultsFormat::Xml.media_type()
+        };
+        let response = self.post(
+            &self.query_endpoint,
+            "application/sparql-query",
+            accept,
+            &query.to_string(),
+        )?;
+        if graph {
+            let format = GraphFormat::from_media_type(response.content_type())
+                .unwrap_or(GraphFormat::NTriples);
+            // Oxigraph has no way to make graph results from triples, so they are loaded into a
+            // store and queried back out.
+            let triples = MemoryStore::new();
+            triples
+                .load_graph(
+                    BufReader::new(response.into_reader()),
+                    format,
+                    &om::GraphName::DefaultGraph,
+                    None,
+                )
+                .map_err(|e| SparqlStoreError::Response(e.to_string()))?;
+            Ok(triples
+                .query("CONSTRUCT WHERE { ?s ?p ?o }")
+                .expect("reading back triples can not fail"))
+        } else {
+            let format = QueryResultsFormat::from_media_type(response.content_type())
+                .unwrap_or(QueryResultsFormat::Xml);
+            QueryResults::read(BufReader::new(response.into_reader()), format)
+                .map_err(|e| SparqlStoreError::Response(e.to_string()))
+        }
+    }
+}
+
+impl Store for SparqlStore {
+    type Error = SparqlStoreError;
+
+    fn insert(&mut self, quad: om::Quad) -> Result<(), SparqlStoreError> {
+        self.commit(&[], vec![quad])
+    }
+
+    fn remove(&mut self, quad: om::QuadRef<'_>) -> Result<(), SparqlStoreError> {
+        self.update(&format!("DELETE DATA {{ {} }}", ground_quad_data(quad)?))
+    }
+
+    fn query(&self, query: Query) -> Result<QueryResults, EvaluationError> {
+        self.evaluate(&query)
+            .map_err(|e| EvaluationError::Io(io::Error::other(e)))
+    }
+
+    fn clear_graph(&mut self, graph: om::GraphNameRef<'_>) -> Result<(), SparqlStoreError> {
+        self.update(&clear(graph)?)
+    }
+
+    fn commit(
+        &mut self,
+        clear_graphs: &[om::GraphName],
+        quads: Vec<om::Quad>,
+    ) -> Result<(), SparqlStoreError> {
+        let mut operations = clear_graphs
+            .iter()
+            .map(|graph| clear(graph.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !quads.is_empty() {
+            let data = quads
+                .iter()
+                .map(|quad| quad_data(quad.as_ref()))
+                .collect::<Result<Vec<_>, _>>()?;
+            operations.push(format!("INSERT DATA {{\n{}\n}}", data.join("\n")));
+        }
+        if operations.is_empty() {
+            return Ok(());
+        }
+        self.update(&operations.join(" ;\n"))
+    }
+}
+
+/// An update operation removing every quad in `graph`.
+fn clear(graph: om::GraphNameRef<'_>) -> Result<String, SparqlStoreError> {
+    match graph {
+        om::GraphNameRef::NamedNode(nn) => Ok(format!("CLEAR SILENT GRAPH {}", nn)),
+        om::GraphNameRef::DefaultGraph => Ok("CLEAR SILENT DEFAULT".into()),
+        om::GraphNameRef::BlankNode(_) => Err(SparqlStoreError::BlankNode),
+    }
+}
+
+/// `quad` in the syntax of the data of an INSERT DATA operation.
+fn quad_data(quad: om::QuadRef<'_>) -> Result<String, SparqlStoreError> {
+    let triple = format!("{} {} {} .", quad.subject, quad.predicate, quad.object);
+    match quad.graph_name {
+        om::GraphNameRef::NamedNode(nn) => Ok(format!("GRAPH {} {{ {} }}", nn, triple)),
+        om::GraphNameRef::DefaultGraph => Ok(triple),
+        om::GraphNameRef::BlankNode(_) => Err(SparqlStoreError::BlankNode),
+    }
+}
+
+/// Like [`quad_data`], for operations where blank nodes would be taken as variables.
+fn ground_quad_data(quad: om::QuadRef<'_>) -> Result<String, SparqlStoreError> {
+    let blank = matches!(quad.subject, om::NamedOrBlankNodeRef::BlankNode(_))
+        || matches!(quad.object, om::TermRef::BlankNode(_));
+    if blank {
+        return Err(SparqlStoreError::BlankNode);
+    }
+    quad_data(quad)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_server::TestServer;
+    use crate::ttl::from_ttl;
+
+    #[test]
+    fn store_operations() {
+        let remote = MemoryStore::new();
+        let server = TestServer::sparql(remote.clone());
+        let mut store = SparqlStore::new(server.url("/query"), server.url("/update"));
+        let g = om::NamedNode::new_unchecked("urn:g");
+        let quad = |ttl: &str| {
+            from_ttl(ttl)
+                .into_iter()
+                .next()
+                .unwrap()
+                .in_graph(g.clone())
+        };
+        let a = quad("<urn:a> <urn:b> <urn:c> .");
+        let b = quad("<urn:a> <urn:b> \"say \\\"hi\\\"\\n\"@en .");
+
+        store.insert(a.clone()).unwrap();
+        assert!(remote.contains(&a));
+        assert!(store.contains(a.as_ref()).unwrap());
+        assert!(!store.contains(b.as_ref()).unwrap());
+        store
+            .replace_graph(
+                g.as_ref().into(),
+                from_ttl("<urn:a> <urn:b> \"say \\\"hi\\\"\\n\"@en . <urn:a> <urn:b> <urn:d> ."),
+            )
+            .unwrap();
+        store
+            .insert(om::Quad::new(g.clone(), g.clone(), g.clone(), None))
+            .unwrap();
+        assert!(!remote.contains(&a));
+        assert!(remote.contains(&b));
+        assert_eq!(store.len().unwrap(), 3);
+        assert_eq!(store.named_graphs().unwrap(), [g.clone().into()]);
+
+        let constructed = Store::query(
+            &store,
+            Query::parse(
+                "CONSTRUCT { ?s ?p ?o } WHERE { GRAPH <urn:g> { ?s ?p ?o } }",
+                None,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        match constructed {
+            QueryResults::Graph(triples) => assert_eq!(triples.count(), 2),
+            _ => panic!(),
+        }
+
+        store.remove(b.as_ref()).unwrap();
+        assert!(!remote.contains(&b));
+        store.clear_graph(om::GraphNameRef::DefaultGraph).unwrap();
+        assert_eq!(remote.len(), 1);
+
+        let blank = om::Quad::new(om::BlankNode::default(), g.clone(), g.clone(), g.clone());
+        assert!(matches!(
+            store.remove(blank.as_ref()),
+            Err(SparqlStoreError::BlankNode)
+        ));
+        let missing = SparqlStore::new(server.url("/missing"), server.url("/missing"));
+        assert!(matches!(
+            missing.len(),
+            Err(SparqlStoreError::Status { status: 404, .. })
+        ));
+    }
+}
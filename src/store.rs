@@ -3,18 +3,375 @@
 
 use oxigraph::model as om;
 use oxigraph::sparql::{algebra::Query, EvaluationError, QueryResults};
+#[cfg(feature = "sled")]
+use std::convert::Infallible;
+use std::io;
 
 pub trait Store {
-    fn insert(&mut self, quad: om::Quad);
+    /// What can go wrong reading from or writing to the store. Failed queries made by the default
+    /// methods are converted into it.
+    type Error: std::error::Error + From<EvaluationError>;
+
+    fn insert(&mut self, quad: om::Quad) -> Result<(), Self::Error>;
+    fn remove(&mut self, quad: om::QuadRef<'_>) -> Result<(), Self::Error>;
     fn query(&self, query: Query) -> Result<QueryResults, EvaluationError>;
+    /// Remove every quad in `graph`.
+    fn clear_graph(&mut self, graph: om::GraphNameRef<'_>) -> Result<(), Self::Error>;
+
+    /// Whether a query whose dataset lists named graphs but no default graph still reads the
+    /// store's default graph, as oxigraph's stores do. SPARQL 1.1 services read an empty default
+    /// graph instead.
+    fn keeps_default_graph(&self) -> bool {
+        false
+    }
+
+    /// Blank nodes in `quad` act as variables, as they do in an ASK query.
+    fn contains(&self, quad: om::QuadRef<'_>) -> Result<bool, Self::Error> {
+        let triple = format!("{} {} {}", quad.subject, quad.predicate, quad.object);
+        let pattern = match quad.graph_name {
+            om::GraphNameRef::NamedNode(nn) => format!("GRAPH {} {{ {} }}", nn, triple),
+            om::GraphNameRef::BlankNode(_) => format!("GRAPH ?g {{ {} }}", triple),
+            om::GraphNameRef::DefaultGraph => triple,
+        };
+        match self.query(parse(&format!("ASK {{ {} }}", pattern)))? {
+            QueryResults::Boolean(b) => Ok(b),
+            _ => Err(unexpected("a boolean")),
+        }
+    }
+
+    /// The number of quads in the store.
+    fn len(&self) -> Result<usize, Self::Error> {
+        let count = select(
+            self,
+            "SELECT (COUNT(*) AS ?n) WHERE { { ?s ?p ?o } UNION { GRAPH ?g { ?s ?p ?o } } }",
+            "n",
+        )?;
+        match count.first() {
+            Some(om::Term::Literal(n)) => n.value().parse().map_err(|_| unexpected("a count")),
+            // Oxigraph leaves the count unbound when there is nothing to count.
+            None => Ok(0),
+            Some(_) => Err(unexpected("a count")),
+        }
+    }
+
+    /// The names of the graphs in the store, in no particular order. Graphs may still be listed
+    /// after they have been cleared.
+    fn named_graphs(&self) -> Result<Vec<om::NamedOrBlankNode>, Self::Error> {
+        select(
+            self,
+            "SELECT DISTINCT ?g WHERE { GRAPH ?g { ?s ?p ?o } }",
+            "g",
+        )?
+        .into_iter()
+        .map(|term| match term {
+            om::Term::NamedNode(nn) => Ok(nn.into()),
+            om::Term::BlankNode(bn) => Ok(bn.into()),
+            om::Term::Literal(_) => Err(unexpected("a graph name")),
+        })
+        .collect()
+    }
+
+    /// Remove every quad in the graphs in `clear`, then insert `quads`. Backends with transactions
+    /// make this a single atomic change; otherwise it may be left partly applied if interrupted.
+    fn commit(&mut self, clear: &[om::GraphName], quads: Vec<om::Quad>) -> Result<(), Self::Error> {
+        for graph in clear {
+            self.clear_graph(graph.as_ref())?;
+        }
+        for quad in quads {
+            self.insert(quad)?;
+        }
+        Ok(())
+    }
+
+    fn is_empty(&self) -> Result<bool, Self::Error> {
+        Ok(self.len()? == 0)
+    }
+
+    fn extend(&mut self, quads: impl IntoIterator<Item = om::Quad>) -> Result<(), Self::Error>
+    where
+        Self: Sized,
+    {
+        for quad in quads {
+            self.insert(quad)?;
+        }
+        Ok(())
+    }
+
+    /// Make `triples` the only contents of `graph`, atomically if the backend supports it.
+    fn replace_graph(
+        &mut self,
+        graph: om::GraphNameRef<'_>,
+        triples: impl IntoIterator<Item = om::Triple>,
+    ) -> Result<(), Self::Error>
+    where
+        Self: Sized,
+    {
+        let graph = graph.into_owned();
+        let quads = triples
+            .into_iter()
+            .map(|t| t.in_graph(graph.clone()))
+            .collect();
+        self.commit(&[graph], quads)
+    }
+}
+
+fn parse(query: &str) -> Query {
+    Query::parse(query, None).expect("query is valid")
+}
+
+fn unexpected<E: From<EvaluationError>>(expected: &str) -> E {
+    EvaluationError::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("expected {} in query results", expected),
+    ))
+    .into()
+}
+
+/// The values bound to `variable` by a SELECT query, skipping solutions leaving it unbound.
+fn select<S: Store + ?Sized>(
+    store: &S,
+    query: &str,
+    variable: &str,
+) -> Result<Vec<om::Term>, S::Error> {
+    let solutions = match store.query(parse(query))? {
+        QueryResults::Solutions(solutions) => solutions,
+        _ => return Err(unexpected("solutions")),
+    };
+    solutions
+        .filter_map(|solution| match solution {
+            Ok(solution) => solution.get(variable).cloned().map(Ok),
+            Err(e) => Some(Err(e.into())),
+        })
+        .collect()
 }
 
 impl Store for oxigraph::store::MemoryStore {
-    fn insert(&mut self, quad: om::Quad) {
-        oxigraph::store::MemoryStore::insert(self, quad)
+    type Error = EvaluationError;
+
+    fn insert(&mut self, quad: om::Quad) -> Result<(), EvaluationError> {
+        oxigraph::store::MemoryStore::insert(self, quad);
+        Ok(())
+    }
+
+    fn remove(&mut self, quad: om::QuadRef<'_>) -> Result<(), EvaluationError> {
+        oxigraph::store::MemoryStore::remove(self, quad);
+        Ok(())
     }
 
     fn query(&self, query: Query) -> Result<QueryResults, EvaluationError> {
         oxigraph::store::MemoryStore::query(self, query)
     }
+
+    fn contains(&self, quad: om::QuadRef<'_>) -> Result<bool, EvaluationError> {
+        Ok(oxigraph::store::MemoryStore::contains(self, quad))
+    }
+
+    fn len(&self) -> Result<usize, EvaluationError> {
+        Ok(oxigraph::store::MemoryStore::len(self))
+    }
+
+    fn named_graphs(&self) -> Result<Vec<om::NamedOrBlankNode>, EvaluationError> {
+        Ok(oxigraph::store::MemoryStore::named_graphs(self).collect())
+    }
+
+    fn clear_graph(&mut self, graph: om::GraphNameRef<'_>) -> Result<(), EvaluationError> {
+        oxigraph::store::MemoryStore::clear_graph(self, graph);
+        Ok(())
+    }
+
+    fn keeps_default_graph(&self) -> bool {
+        true
+    }
+
+    fn commit(
+        &mut self,
+        clear: &[om::GraphName],
+        quads: Vec<om::Quad>,
+    ) -> Result<(), EvaluationError> {
+        let removed: Vec<om::Quad> = clear
+            .iter()
+            .flat_map(|graph| self.quads_for_pattern(None, None, None, Some(graph.as_ref())))
+            .collect();
+        self.transaction(|transaction| {
+            for quad in removed {
+                transaction.remove(quad);
+            }
+            for quad in quads {
+                transaction.insert(quad);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(feature = "sled")]
+impl Store for oxigraph::SledStore {
+    type Error = EvaluationError;
+
+    fn insert(&mut self, quad: om::Quad) -> Result<(), EvaluationError> {
+        Ok(oxigraph::SledStore::insert(self, &quad)?)
+    }
+
+    fn remove(&mut self, quad: om::QuadRef<'_>) -> Result<(), EvaluationError> {
+        Ok(oxigraph::SledStore::remove(self, quad)?)
+    }
+
+    fn query(&self, query: Query) -> Result<QueryResults, EvaluationError> {
+        oxigraph::SledStore::query(self, query)
+    }
+
+    fn contains(&self, quad: om::QuadRef<'_>) -> Result<bool, EvaluationError> {
+        Ok(oxigraph::SledStore::contains(self, quad)?)
+    }
+
+    fn len(&self) -> Result<usize, EvaluationError> {
+        Ok(oxigraph::SledStore::len(self))
+    }
+
+    fn named_graphs(&self) -> Result<Vec<om::NamedOrBlankNode>, EvaluationError> {
+        Ok(oxigraph::SledStore::named_graphs(self).collect::<io::Result<_>>()?)
+    }
+
+    fn clear_graph(&mut self, graph: om::GraphNameRef<'_>) -> Result<(), EvaluationError> {
+        Ok(oxigraph::SledStore::clear_graph(self, graph)?)
+    }
+
+    fn keeps_default_graph(&self) -> bool {
+        true
+    }
+
+    fn commit(
+        &mut self,
+        clear: &[om::GraphName],
+        quads: Vec<om::Quad>,
+    ) -> Result<(), EvaluationError> {
+        use oxigraph::store::sled::{SledConflictableTransactionError, SledTransactionError};
+
+        let removed: Vec<om::Quad> = clear
+            .iter()
+            .flat_map(|graph| self.quads_for_pattern(None, None, None, Some(graph.as_ref())))
+            .collect::<io::Result<_>>()?;
+        // Transactions don't add graphs to the list of named graphs, so that is done beforehand.
+        // An interrupted commit may leave a graph listed but empty, which is allowed.
+        for graph in quads.iter().filter_map(|quad| match &quad.graph_name {
+            om::GraphName::NamedNode(nn) => Some(om::NamedOrBlankNodeRef::from(nn.as_ref())),
+            om::GraphName::BlankNode(bn) => Some(bn.as_ref().into()),
+            om::GraphName::DefaultGraph => None,
+        }) {
+            self.insert_named_graph(graph)?;
+        }
+        self.transaction(|transaction| {
+            for quad in &removed {
+                transaction.remove(quad)?;
+            }
+            for quad in &quads {
+                transaction.insert(quad)?;
+            }
+            Ok::<_, SledConflictableTransactionError<Infallible>>(())
+        })
+        .map_err(|e| match e {
+            SledTransactionError::Abort(e) => match e {},
+            SledTransactionError::Storage(e) => e.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ttl::from_ttl;
+    use oxigraph::store::MemoryStore;
+
+    #[test]
+    fn replace_graph() {
+        let g = om::NamedNode::new_unchecked("urn:g");
+        let h = om::NamedNode::new_unchecked("urn:h");
+        let mut store = MemoryStore::new();
+        assert!(Store::is_empty(&store).unwrap());
+        Store::extend(
+            &mut store,
+            from_ttl("<urn:a> <urn:b> <urn:c> . <urn:a> <urn:b> <urn:d> .")
+                .into_iter()
+                .map(|t| t.in_graph(g.clone())),
+        )
+        .unwrap();
+        Store::extend(
+            &mut store,
+            from_ttl("<urn:a> <urn:b> <urn:c> .")
+                .into_iter()
+                .map(|t| t.in_graph(h.clone())),
+        )
+        .unwrap();
+        assert_eq!(Store::len(&store).unwrap(), 3);
+
+        let replacement = from_ttl("<urn:a> <urn:b> <urn:e> .");
+        let quad = replacement
+            .clone()
+            .into_iter()
+            .next()
+            .unwrap()
+            .in_graph(g.clone());
+        assert!(!Store::contains(&store, quad.as_ref()).unwrap());
+        Store::replace_graph(&mut store, g.as_ref().into(), replacement).unwrap();
+        assert!(Store::contains(&store, quad.as_ref()).unwrap());
+        assert_eq!(Store::len(&store).unwrap(), 2);
+
+        Store::remove(&mut store, quad.as_ref()).unwrap();
+        assert_eq!(Store::len(&store).unwrap(), 1);
+        let mut graphs = Store::named_graphs(&store).unwrap();
+        graphs.sort_by_key(|g| g.to_string());
+        assert_eq!(graphs, [g.into(), h.into()]);
+    }
+
+    /// A store providing only the required methods.
+    struct Minimal(MemoryStore);
+
+    impl Store for Minimal {
+        type Error = EvaluationError;
+
+        fn insert(&mut self, quad: om::Quad) -> Result<(), EvaluationError> {
+            Store::insert(&mut self.0, quad)
+        }
+
+        fn remove(&mut self, quad: om::QuadRef<'_>) -> Result<(), EvaluationError> {
+            Store::remove(&mut self.0, quad)
+        }
+
+        fn query(&self, query: Query) -> Result<QueryResults, EvaluationError> {
+            Store::query(&self.0, query)
+        }
+
+        fn clear_graph(&mut self, graph: om::GraphNameRef<'_>) -> Result<(), EvaluationError> {
+            Store::clear_graph(&mut self.0, graph)
+        }
+    }
+
+    #[test]
+    fn default_reads() {
+        let g = om::NamedNode::new_unchecked("urn:g");
+        let mut store = Minimal(MemoryStore::new());
+        assert!(store.is_empty().unwrap());
+        assert_eq!(store.named_graphs().unwrap(), []);
+
+        let triples: Vec<om::Triple> =
+            from_ttl("<urn:a> <urn:b> <urn:c> . <urn:a> <urn:b> \"d\" .")
+                .into_iter()
+                .collect();
+        let quad = triples[1].clone().in_graph(g.clone());
+        assert!(!store.contains(quad.as_ref()).unwrap());
+        store
+            .extend(triples.iter().cloned().map(|t| t.in_graph(g.clone())))
+            .unwrap();
+        store
+            .extend(
+                triples
+                    .into_iter()
+                    .map(|t| t.in_graph(om::GraphName::DefaultGraph)),
+            )
+            .unwrap();
+        assert_eq!(store.len().unwrap(), store.0.len());
+        assert_eq!(store.len().unwrap(), 4);
+        assert!(store.contains(quad.as_ref()).unwrap());
+        assert_eq!(store.named_graphs().unwrap(), [g.into()]);
+    }
 }
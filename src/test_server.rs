@@ -0,0 +1,184 @@
+//! A tiny http server for testing lookups without touching the network.
+
+use alloc::collections::BTreeMap;
+use oxigraph::io::GraphFormat;
+use oxigraph::model as om;
+use oxigraph::sparql::{QueryResults, QueryResultsFormat};
+use oxigraph::MemoryStore;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercased.
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn ok(content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self::status(200)
+            .header("Content-Type", content_type)
+            .body(body)
+    }
+
+    pub fn redirect(location: &str) -> Self {
+        Self::status(303).header("Location", location)
+    }
+
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+/// Serves each request on a background thread by calling a handler. Every request received is
+/// kept so tests can inspect what was sent.
+pub struct TestServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl TestServer {
+    pub fn start(handler: impl Fn(&Request) -> Response + Send + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let request = match read_request(&mut stream) {
+                    Some(request) => request,
+                    None => continue,
+                };
+                let response = handler(&request);
+                log.lock().unwrap().push(request);
+                let _ = write_response(&mut stream, response);
+            }
+        });
+        Self { addr, requests }
+    }
+
+    /// A stand-in for a triplestore such as oxigraph's server, keeping its data in `store`. SPARQL
+    /// queries are answered at `/query` and updates at `/update`, both sent directly in the body
+    /// of a POST.
+    pub fn sparql(store: MemoryStore) -> Self {
+        Self::start(move |req| {
+            let body = String::from_utf8_lossy(&req.body);
+            match (req.method.as_str(), req.path.as_str()) {
+                ("POST", "/query") => match store.query(body.as_ref()) {
+                    Ok(QueryResults::Graph(triples)) => {
+                        let mut out = Vec::new();
+                        QueryResults::Graph(triples)
+                            .write_graph(&mut out, GraphFormat::NTriples)
+                            .unwrap();
+                        Response::ok(GraphFormat::NTriples.media_type(), out)
+                    }
+                    Ok(results) => {
+                        let mut out = Vec::new();
+                        results.write(&mut out, QueryResultsFormat::Xml).unwrap();
+                        Response::ok(QueryResultsFormat::Xml.media_type(), out)
+                    }
+                    Err(e) => Response::status(400).body(e.to_string()),
+                },
+                ("POST", "/update") => match store.update(body.as_ref()) {
+                    Ok(()) => Response::status(204),
+                    Err(e) => Response::status(400).body(e.to_string()),
+                },
+                _ => Response::status(404),
+            }
+        })
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub fn iri(&self, path: &str) -> om::NamedNode {
+        om::NamedNode::new(self.url(path)).unwrap()
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        core::mem::take(&mut *self.requests.lock().unwrap())
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut words = line.split_whitespace();
+    let method = words.next()?.to_string();
+    let path = words.next()?.to_string();
+    let mut headers = BTreeMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let mut kv = line.splitn(2, ':');
+        let key = kv.next()?.trim().to_ascii_lowercase();
+        let value = kv.next()?.trim().to_string();
+        headers.insert(key, value);
+    }
+    let mut body = Vec::new();
+    if let Some(len) = headers.get("content-length") {
+        reader.take(len.parse().ok()?).read_to_end(&mut body).ok()?;
+    }
+    Some(Request {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+fn write_response(stream: &mut TcpStream, response: Response) -> std::io::Result<()> {
+    write!(stream, "HTTP/1.1 {} Test\r\n", response.status)?;
+    for (name, value) in &response.headers {
+        write!(stream, "{}: {}\r\n", name, value)?;
+    }
+    write!(
+        stream,
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+/// Run `f` to completion on a tokio 1 runtime, which reqwest needs.
+pub fn block_on<F: core::future::Future>(f: F) -> F::Output {
+    tokio1::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(f)
+}